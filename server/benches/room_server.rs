@@ -0,0 +1,154 @@
+//! Throughput of the room server with thousands of rooms and sessions, for a growing number of
+//! shards.
+//!
+//! `cargo bench --bench room_server -- [rooms] [sessions] [messages]`, defaulting to 2,000
+//! rooms, 20,000 sessions and 50,000 broadcasts.
+
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc;
+
+// the crate is a binary, so the benchmark builds the room server from its sources
+#[allow(dead_code)]
+#[path = "../src/metrics.rs"]
+mod metrics;
+#[allow(dead_code)]
+#[path = "../src/server.rs"]
+mod server;
+
+use server::{RoomServer, RoomServerHandle};
+
+const BROADCAST: &str = "bench";
+
+/// Broadcasts in flight at once, as if sent by that many request handlers.
+const SENDERS: usize = 64;
+
+struct Run {
+    connects: f64,
+    messages: f64,
+    deliveries: f64,
+    disconnects: f64,
+}
+
+#[tokio::main]
+async fn main() {
+    // `cargo bench` passes `--bench` along with the arguments given after `--`
+    let args = env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .map(|arg| arg.parse::<usize>().expect("arguments are counts"))
+        .collect::<Vec<_>>();
+    let rooms = args.first().copied().unwrap_or(2_000);
+    let sessions = args.get(1).copied().unwrap_or(20_000);
+    let messages = args.get(2).copied().unwrap_or(50_000);
+
+    let cpus = thread::available_parallelism().map_or(1, usize::from);
+    let mut shards = vec![1, 2, 4, 8, cpus];
+    shards.sort_unstable();
+    shards.dedup();
+
+    println!("{rooms} rooms, {sessions} sessions, {messages} broadcasts, {cpus} CPUs");
+    println!(
+        "{:>6} {:>12} {:>12} {:>14} {:>14}",
+        "shards", "connects/s", "messages/s", "deliveries/s", "disconnects/s"
+    );
+
+    for shards in shards {
+        let run = run(shards, rooms, sessions, messages).await;
+        println!(
+            "{shards:>6} {:>12.0} {:>12.0} {:>14.0} {:>14.0}",
+            run.connects, run.messages, run.deliveries, run.disconnects
+        );
+    }
+}
+
+async fn run(shards: usize, rooms: usize, sessions: usize, messages: usize) -> Run {
+    // no coalescing, every broadcast reaches every session of its room
+    let (room_server, handle) = RoomServer::new(shards, Duration::ZERO);
+    let room_server = tokio::spawn(room_server.run());
+
+    let room_ids = (0..rooms)
+        .map(|room| format!("{room:024x}"))
+        .collect::<Arc<[_]>>();
+    let delivered = Arc::new(AtomicU64::new(0));
+
+    let started = Instant::now();
+    let mut connected = Vec::with_capacity(sessions);
+    for session in 0..sessions {
+        let (conn_tx, mut conn_rx) = mpsc::unbounded_channel::<String>();
+        let room_id = room_ids[session % rooms].clone();
+        let (id, _) = handle.connect(conn_tx, room_id.clone(), None).await;
+
+        let delivered = delivered.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = conn_rx.recv().await {
+                // join and leave notices are not part of the broadcasts
+                if msg == BROADCAST {
+                    delivered.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+        connected.push((room_id, id));
+    }
+    let connects = sessions as f64 / started.elapsed().as_secs_f64();
+
+    let started = Instant::now();
+    let senders = (0..SENDERS)
+        .map(|sender| {
+            let handle = handle.clone();
+            let room_ids = room_ids.clone();
+            tokio::spawn(broadcast(handle, room_ids, sender, messages))
+        })
+        .collect::<Vec<_>>();
+    for sender in senders {
+        sender.await.unwrap();
+    }
+    let sent = started.elapsed().as_secs_f64();
+
+    // broadcasts are queued once `send_message` returns, wait until every session read them
+    let expected = (0..messages)
+        .map(|message| sessions_in(message % rooms, rooms, sessions))
+        .sum::<u64>();
+    while delivered.load(Ordering::Relaxed) < expected {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let read = started.elapsed().as_secs_f64();
+
+    let started = Instant::now();
+    for (room_id, id) in &connected {
+        handle.disconnect(*id, [room_id.as_str()]);
+    }
+    // each shard answers once it went through the disconnects queued before
+    handle.stats().await;
+    let disconnects = sessions as f64 / started.elapsed().as_secs_f64();
+
+    drop(handle);
+    room_server.await.unwrap().unwrap();
+
+    Run {
+        connects,
+        messages: messages as f64 / sent,
+        deliveries: expected as f64 / read,
+        disconnects,
+    }
+}
+
+/// Sessions connected to `room`, they are dealt to the rooms in turn.
+fn sessions_in(room: usize, rooms: usize, sessions: usize) -> u64 {
+    (sessions / rooms + usize::from(room < sessions % rooms)) as u64
+}
+
+async fn broadcast(handle: RoomServerHandle, room_ids: Arc<[String]>, sender: usize, total: usize) {
+    for message in (sender..total).step_by(SENDERS) {
+        let room_id = room_ids[message % room_ids.len()].clone();
+        handle.send_message(room_id, BROADCAST).await;
+    }
+}
@@ -0,0 +1,325 @@
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use wither::bson::{self, doc, DateTime, Document};
+use wither::mongodb::{
+    change_stream::event::{ChangeStreamEvent, OperationType, ResumeToken},
+    error::ErrorKind,
+    options::{ChangeStreamOptions, FullDocumentBeforeChangeType, FullDocumentType, UpdateOptions},
+};
+use wither::Model;
+
+use crate::{
+    database,
+    errors::Error,
+    metrics::time_db,
+    models::{
+        poll::{Poll, PublicPoll},
+        question::{PublicQuestion, Question, ReactionCounts},
+        room::{PublicAnnouncement, PublicRoom, Room},
+    },
+    server::RoomServerHandle,
+    services::spotlight,
+    settings,
+    utils::message_data::MessageData,
+};
+
+const RESUME_TOKENS_COLLECTION: &str = "resume_tokens";
+
+/// Fields of a question that votes update, besides the per-kind `reactions.{kind}` counts.
+const VOTE_FIELDS: [&str; 4] = ["reaction_count", "downvotes", "confidence", "hidden"];
+
+/// Server error codes meaning a stored resume token can no longer be used.
+const CHANGE_STREAM_HISTORY_LOST: i32 = 286;
+const INVALID_RESUME_TOKEN: i32 = 260;
+const NAMESPACE_NOT_FOUND: i32 = 26;
+
+/// Broadcast changes to the rooms, questions and polls collections, whoever made them.
+///
+/// The resume token of the last broadcast change is stored after each one, so a restart picks
+/// up where the previous process stopped. Runs until aborted, reconnecting after errors.
+pub async fn watch(room_server: RoomServerHandle) {
+    let retry = settings::get().change_streams.retry();
+
+    loop {
+        if let Err(err) = run(&room_server).await {
+            tracing::error!(error = %err, "change stream failed, reconnecting");
+        }
+        tokio::time::sleep(retry).await;
+    }
+}
+
+async fn run(room_server: &RoomServerHandle) -> Result<(), Error> {
+    let connection = database::connection().await;
+    enable_pre_images(Question::COLLECTION_NAME).await?;
+
+    let pipeline = [doc! { "$match": {
+        "ns.coll": {
+            "$in": [Room::COLLECTION_NAME, Question::COLLECTION_NAME, Poll::COLLECTION_NAME],
+        },
+    } }];
+
+    let token = load_token().await?;
+    let resumed = token.is_some();
+    let mut stream = match connection.watch(pipeline.clone(), options(token)).await {
+        Err(err) if resumed && is_token_lost(&err) => {
+            tracing::warn!(error = %err, "stored resume token expired, changes were missed");
+            connection.watch(pipeline, options(None)).await?
+        }
+        result => result?,
+    };
+    tracing::info!(resumed, "watching change streams");
+
+    while let Some(change) = stream.next().await {
+        let change = change?;
+        if let Err(err) = broadcast(room_server, &change).await {
+            tracing::error!(error = %err, "failed to broadcast change");
+        }
+        save_token(&change.id).await?;
+    }
+
+    Ok(())
+}
+
+/// Have change streams carry documents as they were before the change: the pre-image of a
+/// deleted question is all that tells which room to broadcast to. Creates the collection when
+/// it does not exist yet.
+async fn enable_pre_images(collection: &str) -> Result<(), Error> {
+    let connection = database::connection().await;
+    let pre_images = doc! { "enabled": true };
+
+    let result = connection
+        .run_command(
+            doc! { "collMod": collection, "changeStreamPreAndPostImages": pre_images.clone() },
+            None,
+        )
+        .await;
+    match result {
+        Err(err) if is_code(&err, NAMESPACE_NOT_FOUND) => {
+            connection
+                .run_command(
+                    doc! { "create": collection, "changeStreamPreAndPostImages": pre_images },
+                    None,
+                )
+                .await?;
+        }
+        result => {
+            result?;
+        }
+    }
+
+    Ok(())
+}
+
+fn options(resume_after: Option<ResumeToken>) -> ChangeStreamOptions {
+    ChangeStreamOptions::builder()
+        .full_document(Some(FullDocumentType::UpdateLookup))
+        // missing for changes made before pre-images were enabled
+        .full_document_before_change(Some(FullDocumentBeforeChangeType::WhenAvailable))
+        .resume_after(resume_after)
+        .build()
+}
+
+fn is_token_lost(err: &wither::mongodb::error::Error) -> bool {
+    is_code(err, CHANGE_STREAM_HISTORY_LOST) || is_code(err, INVALID_RESUME_TOKEN)
+}
+
+fn is_code(err: &wither::mongodb::error::Error, code: i32) -> bool {
+    matches!(*err.kind, ErrorKind::Command(ref command) if command.code == code)
+}
+
+async fn broadcast(
+    room_server: &RoomServerHandle,
+    change: &ChangeStreamEvent<Document>,
+) -> Result<(), Error> {
+    let collection = change.ns.as_ref().and_then(|ns| ns.coll.as_deref());
+
+    match (collection, &change.operation_type) {
+        (Some(Question::COLLECTION_NAME), OperationType::Insert) => {
+            if let Some(question) = decode::<Question>(&change.full_document)? {
+                let room_id = question.room_id.to_string();
+                let involves = question.involves();
+                let public_question = PublicQuestion::from(question);
+                room_server
+                    .send_message_involving(
+                        room_id,
+                        MessageData::create(&public_question),
+                        involves,
+                    )
+                    .await;
+            }
+        }
+        (Some(Question::COLLECTION_NAME), OperationType::Update | OperationType::Replace) => {
+            // `None` when the question was deleted before the lookup
+            if let Some(question) = decode::<Question>(&change.full_document)? {
+                let room_id = question.room_id.to_string();
+                let involves = question.involves();
+                match reaction_counts(change, &question) {
+                    Some(counts) => {
+                        room_server.send_coalesced(
+                            room_id,
+                            counts.question_id.to_hex(),
+                            MessageData::reaction(&counts),
+                            involves,
+                        );
+                    }
+                    None => {
+                        let public_question = PublicQuestion::from(question);
+                        room_server
+                            .send_message_involving(
+                                room_id,
+                                MessageData::update(&public_question),
+                                involves,
+                            )
+                            .await;
+                    }
+                }
+            }
+        }
+        (Some(Question::COLLECTION_NAME), OperationType::Delete) => {
+            let id = change
+                .document_key
+                .as_ref()
+                .and_then(|key| key.get_object_id("_id").ok());
+            let before = decode::<Question>(&change.full_document_before_change)?;
+
+            match (id, before) {
+                (Some(id), Some(question)) => {
+                    room_server
+                        .send_message_involving(
+                            question.room_id.to_string(),
+                            MessageData::delete(&json!({ "id": id.to_hex() })),
+                            question.involves(),
+                        )
+                        .await;
+                }
+                _ => tracing::debug!("skipping deleted question without a pre-image"),
+            }
+        }
+        (
+            Some(Poll::COLLECTION_NAME),
+            OperationType::Insert | OperationType::Update | OperationType::Replace,
+        ) => {
+            if let Some(poll) = decode::<Poll>(&change.full_document)? {
+                let room_id = poll.room_id.to_string();
+                let public_poll = PublicPoll::from(poll);
+                room_server
+                    .send_message(room_id, MessageData::poll(&public_poll))
+                    .await;
+            }
+        }
+        (Some(Room::COLLECTION_NAME), OperationType::Update | OperationType::Replace) => {
+            let closed_now = match &change.update_description {
+                Some(update) => update.updated_fields.get_bool("closed") == Ok(true),
+                None => true,
+            };
+
+            let spotlighted = change
+                .update_description
+                .as_ref()
+                .is_some_and(|update| update.updated_fields.contains_key("spotlight"));
+
+            // `$push` with `$slice` reports either the whole array or its new items
+            let announced = change.update_description.as_ref().is_some_and(|update| {
+                update
+                    .updated_fields
+                    .keys()
+                    .any(|field| field.starts_with("announcements"))
+            });
+
+            if let Some(room) = decode::<Room>(&change.full_document)? {
+                if spotlighted {
+                    spotlight::broadcast(room_server, &room).await?;
+                }
+                if let Some(announcement) = room.announcements.last().filter(|_| announced) {
+                    let room_id = room.id.map(|id| id.to_string()).unwrap_or_default();
+                    let public_announcement = PublicAnnouncement::from(announcement.clone());
+                    room_server
+                        .send_message(room_id, MessageData::announcement(&public_announcement))
+                        .await;
+                }
+                if closed_now && room.closed {
+                    let room_id = room.id.map(|id| id.to_string()).unwrap_or_default();
+                    let public_room = PublicRoom::from(room);
+                    room_server
+                        .send_message(room_id, MessageData::close(&public_room))
+                        .await;
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// The counts of the question when an update only touched its votes.
+///
+fn reaction_counts(
+    change: &ChangeStreamEvent<Document>,
+    question: &Question,
+) -> Option<ReactionCounts> {
+    let update = change.update_description.as_ref()?;
+    let only_votes = !update.updated_fields.is_empty()
+        && update.removed_fields.is_empty()
+        && update
+            .updated_fields
+            .keys()
+            .all(|field| VOTE_FIELDS.contains(&field.as_str()) || field.starts_with("reactions."));
+
+    if only_votes {
+        ReactionCounts::new(question)
+    } else {
+        None
+    }
+}
+
+fn decode<T: DeserializeOwned>(document: &Option<Document>) -> Result<Option<T>, Error> {
+    document
+        .clone()
+        .map(bson::from_document)
+        .transpose()
+        .map_err(|err| Error::InternalServerError(format!("Failed to decode change: {err}")))
+}
+
+async fn load_token() -> Result<Option<ResumeToken>, Error> {
+    let connection = database::connection().await;
+    let key = &settings::get().change_streams.resume_key;
+
+    let stored = time_db(
+        RESUME_TOKENS_COLLECTION,
+        "find_one",
+        connection
+            .collection::<Document>(RESUME_TOKENS_COLLECTION)
+            .find_one(doc! { "_id": key }, None),
+    )
+    .await?;
+
+    stored
+        .and_then(|document| document.get("token").cloned())
+        .map(bson::from_bson)
+        .transpose()
+        .map_err(|err| Error::InternalServerError(format!("Invalid resume token: {err}")))
+}
+
+async fn save_token(token: &ResumeToken) -> Result<(), Error> {
+    let connection = database::connection().await;
+    let key = &settings::get().change_streams.resume_key;
+    let token = bson::to_bson(token).map_err(|err| Error::InternalServerError(err.to_string()))?;
+    let options = UpdateOptions::builder().upsert(true).build();
+
+    time_db(
+        RESUME_TOKENS_COLLECTION,
+        "update_one",
+        connection
+            .collection::<Document>(RESUME_TOKENS_COLLECTION)
+            .update_one(
+                doc! { "_id": key },
+                doc! { "$set": { "token": token, "updated_at": DateTime::now() } },
+                options,
+            ),
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::{
+    database,
+    errors::{Error, ErrorResponse},
+    models::event::Actor,
+    services::{
+        import::{self, RoomImport},
+        projection,
+    },
+    utils::to_object_id::to_object_id,
+};
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub config_path: Option<String>,
+    pub print_config: bool,
+    pub import: Option<ImportArgs>,
+    /// Room to rebuild from its event log, `--rebuild <room_id>`.
+    pub rebuild: Option<String>,
+}
+
+/// `--import <file>`, with the room given by `--room-key` and `--room-name` for CSV files.
+///
+#[derive(Debug, Default)]
+pub struct ImportArgs {
+    pub path: PathBuf,
+    pub room_key: Option<String>,
+    pub room_name: Option<String>,
+}
+
+impl Args {
+    /// Parse the process arguments, `--config <path>`, `--print-config`, `--rebuild` and the
+    /// import options are supported.
+    pub fn parse() -> io::Result<Self> {
+        let mut args = Args::default();
+        let mut import_path = None;
+        let mut room_key = None;
+        let mut room_name = None;
+        let mut iter = std::env::args().skip(1);
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--config" => args.config_path = Some(value(&mut iter, "--config", "a file path")?),
+                "--print-config" => args.print_config = true,
+                "--import" => import_path = Some(value(&mut iter, "--import", "a file path")?),
+                "--room-key" => room_key = Some(value(&mut iter, "--room-key", "a key")?),
+                "--room-name" => room_name = Some(value(&mut iter, "--room-name", "a name")?),
+                "--rebuild" => args.rebuild = Some(value(&mut iter, "--rebuild", "a room id")?),
+                other => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown argument {other}"),
+                    ))
+                }
+            }
+        }
+
+        args.import = import_path.map(|path| ImportArgs {
+            path: PathBuf::from(path),
+            room_key,
+            room_name,
+        });
+
+        Ok(args)
+    }
+}
+
+fn value(iter: &mut impl Iterator<Item = String>, flag: &str, expects: &str) -> io::Result<String> {
+    iter.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{flag} expects {expects}"),
+        )
+    })
+}
+
+/// Import a CSV or JSON file straight into the database and print the report.
+///
+pub async fn run_import(args: &ImportArgs) -> io::Result<()> {
+    let data = fs::read(&args.path)?;
+    let is_csv = args
+        .path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+
+    let parsed = if is_csv {
+        import::parse_csv(&data).and_then(|rows| {
+            let (Some(key), Some(name)) = (&args.room_key, &args.room_name) else {
+                return Err(Error::bad_request(
+                    "CSV imports need --room-key and --room-name".into(),
+                ));
+            };
+            let room_import = RoomImport {
+                key: key.clone(),
+                name: name.clone(),
+                slow_mode_secs: None,
+            };
+            Ok((room_import, rows))
+        })
+    } else {
+        import::parse_json(&data).map(|(mut room_import, rows)| {
+            room_import.key = args.room_key.clone().unwrap_or(room_import.key);
+            room_import.name = args.room_name.clone().unwrap_or(room_import.name);
+            (room_import, rows)
+        })
+    };
+
+    let result = match parsed {
+        Ok((room_import, rows)) => {
+            // the unique indexes keep concurrent imports from creating the room twice
+            database::sync_indexes().await.map_err(io::Error::other)?;
+            import::import(&Actor::cli(), room_import, rows).await
+        }
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(report) => {
+            let output = serde_json::to_string_pretty(&report).map_err(io::Error::other)?;
+            println!("{output}");
+            Ok(())
+        }
+        Err(err) => {
+            let output = serde_json::to_string_pretty(&ErrorResponse::from(&err))
+                .map_err(io::Error::other)?;
+            eprintln!("{output}");
+            Err(io::Error::new(io::ErrorKind::InvalidData, err))
+        }
+    }
+}
+
+/// Rebuild a room and its questions from the event log and print the result.
+///
+pub async fn run_rebuild(room_id: &str) -> io::Result<()> {
+    let room_id = to_object_id(room_id)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid room id"))?;
+
+    match projection::rebuild(room_id).await {
+        Ok(rebuild) => {
+            let output = serde_json::to_string_pretty(&rebuild).map_err(io::Error::other)?;
+            println!("{output}");
+            Ok(())
+        }
+        Err(err) => {
+            let output = serde_json::to_string_pretty(&ErrorResponse::from(&err))
+                .map_err(io::Error::other)?;
+            eprintln!("{output}");
+            Err(io::Error::other(err))
+        }
+    }
+}
@@ -1,24 +1,155 @@
-use std::env;
+use std::{future::Future, sync::Arc, time::Duration};
 
-use dotenv::dotenv;
-use mongodb::Database;
-use tokio::sync::OnceCell;
-use wither::mongodb;
+use mongodb::{
+    bson::doc,
+    error::{TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT},
+    options::ClientOptions,
+    Client, ClientSession, Database,
+};
+use tokio::sync::{Mutex, OnceCell};
+use wither::{mongodb, Model, WitherError};
 
+use crate::{
+    errors::Error,
+    models::{
+        event::Event,
+        poll::{Poll, PollVote},
+        question::{Question, QuestionDownvote, QuestionReaction},
+        room::Room,
+        webhook::{Webhook, WebhookDelivery},
+    },
+    settings,
+};
+
+static CLIENT: OnceCell<Client> = OnceCell::const_new();
 static CONNECTION: OnceCell<Database> = OnceCell::const_new();
 
-pub async fn connection() -> &'static Database {
-    CONNECTION
-        .get_or_init(|| async {
-            dotenv().ok();
+/// Attempts at a whole transaction when the server reports a transient error, e.g. a write
+/// conflict with a concurrent transaction.
+const MAX_TRANSACTION_ATTEMPTS: usize = 5;
 
-            let db_uri = env::var("DATABASE_URL").expect("missing DATABASE_URL env variable");
-            let db_name = env::var("DATABASE_NAME").expect("missing DATABASE_NAME env variable");
+/// Attempts at committing when the outcome of the previous commit is unknown.
+const MAX_COMMIT_ATTEMPTS: usize = 3;
+
+tokio::task_local! {
+    static SESSION: Arc<Mutex<ClientSession>>;
+}
+
+async fn client() -> &'static Client {
+    CLIENT
+        .get_or_init(|| async {
+            let settings = &settings::get().database;
 
-            mongodb::Client::with_uri_str(&db_uri)
+            let mut options = ClientOptions::parse(&settings.url)
                 .await
-                .expect("Failed to initialize MongoDB connection")
-                .database(&db_name)
+                .expect("Failed to parse MongoDB connection string");
+            options.max_pool_size = settings.max_pool_size.or(options.max_pool_size);
+            options.connect_timeout = settings
+                .connect_timeout_secs
+                .map(Duration::from_secs)
+                .or(options.connect_timeout);
+
+            Client::with_options(options).expect("Failed to initialize MongoDB connection")
         })
         .await
 }
+
+pub async fn connection() -> &'static Database {
+    CONNECTION
+        .get_or_init(|| async { client().await.database(&settings::get().database.name) })
+        .await
+}
+
+/// The session of the transaction this task is running in, if any.
+///
+pub fn session() -> Option<Arc<Mutex<ClientSession>>> {
+    SESSION.try_with(Arc::clone).ok()
+}
+
+/// Run `operations` in a transaction, all over again when it hits a transient error.
+///
+/// Writes made through [`ModelExt`](crate::utils::models::ModelExt) join the transaction on
+/// their own, reads do not. Transactions need MongoDB to run as a replica set.
+pub async fn transaction<T, F, Fut>(operations: F) -> Result<T, Error>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    if session().is_some() {
+        return operations().await;
+    }
+
+    let session = client().await.start_session(None).await?;
+    let session = Arc::new(Mutex::new(session));
+
+    let mut attempt = 1;
+    loop {
+        session.lock().await.start_transaction(None).await?;
+
+        let result = match SESSION.scope(session.clone(), operations()).await {
+            Ok(value) => commit(&mut *session.lock().await).await.map(|()| value),
+            Err(err) => {
+                // the server may have aborted it already
+                let _ = session.lock().await.abort_transaction().await;
+                Err(err)
+            }
+        };
+
+        match result {
+            Err(err)
+                if attempt < MAX_TRANSACTION_ATTEMPTS
+                    && has_label(&err, TRANSIENT_TRANSACTION_ERROR) =>
+            {
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn commit(session: &mut ClientSession) -> Result<(), Error> {
+    let mut attempt = 1;
+    loop {
+        match session.commit_transaction().await.map_err(Error::Mongo) {
+            Err(err)
+                if attempt < MAX_COMMIT_ATTEMPTS
+                    && has_label(&err, UNKNOWN_TRANSACTION_COMMIT_RESULT) =>
+            {
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn has_label(err: &Error, label: &str) -> bool {
+    match err {
+        Error::Mongo(err) | Error::Wither(WitherError::Mongo(err)) => err.contains_label(label),
+        _ => false,
+    }
+}
+
+/// Check the server is reachable, unlike [`connection`] which connects lazily.
+///
+pub async fn ping() -> Result<(), mongodb::error::Error> {
+    connection()
+        .await
+        .run_command(doc! { "ping": 1 }, None)
+        .await
+        .map(|_| ())
+}
+
+/// Create the indexes declared on the models, the text index behind search among them.
+///
+pub async fn sync_indexes() -> Result<(), wither::WitherError> {
+    let connection = connection().await;
+    Room::sync(connection).await?;
+    Question::sync(connection).await?;
+    QuestionDownvote::sync(connection).await?;
+    QuestionReaction::sync(connection).await?;
+    Webhook::sync(connection).await?;
+    WebhookDelivery::sync(connection).await?;
+    Event::sync(connection).await?;
+    Poll::sync(connection).await?;
+    PollVote::sync(connection).await
+}
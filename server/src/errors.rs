@@ -1,8 +1,15 @@
-use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use std::time::Duration;
+
+use actix_web::{
+    http::{header, StatusCode},
+    HttpResponse, ResponseError,
+};
 use serde::Serialize;
-use wither::mongodb::error::Error as MongoError;
+use wither::mongodb::error::{Error as MongoError, ErrorKind, WriteFailure};
 use wither::WitherError;
 
+use crate::{models::question::SimilarQuestion, services::import::RowError};
+
 #[derive(Debug, thiserror::Error)]
 #[error("...")]
 pub enum Error {
@@ -18,6 +25,27 @@ pub enum Error {
     #[error("{0}")]
     BadRequest(String),
 
+    #[error("{0}")]
+    Unauthorized(String),
+
+    #[error("{0}")]
+    Forbidden(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    #[error("{message}")]
+    TooManyRequests {
+        message: String,
+        retry_after: Duration,
+    },
+
+    #[error("Similar questions have already been asked")]
+    DuplicateQuestion { suggestions: Vec<SimilarQuestion> },
+
+    #[error("Some rows cannot be imported")]
+    InvalidImport { rows: Vec<RowError> },
+
     #[error("{0}")]
     Wither(#[from] WitherError),
 
@@ -29,6 +57,37 @@ impl Error {
     pub fn bad_request(message: String) -> Self {
         Error::BadRequest(message)
     }
+
+    pub fn too_many_requests(message: impl Into<String>, retry_after: Duration) -> Self {
+        Error::TooManyRequests {
+            message: message.into(),
+            retry_after,
+        }
+    }
+
+    /// Whole seconds a client should wait before retrying, rounded up.
+    ///
+    pub fn retry_after_secs(&self) -> Option<u64> {
+        match self {
+            Error::TooManyRequests { retry_after, .. } => {
+                Some(retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Server error code of a write refused by a unique index.
+const DUPLICATE_KEY: i32 = 11000;
+
+/// Whether MongoDB refused a write because the document already exists.
+///
+pub fn is_duplicate_key(err: &MongoError) -> bool {
+    match *err.kind {
+        ErrorKind::Write(WriteFailure::WriteError(ref error)) => error.code == DUPLICATE_KEY,
+        ErrorKind::Command(ref error) => error.code == DUPLICATE_KEY,
+        _ => false,
+    }
 }
 
 impl ResponseError for Error {
@@ -38,20 +97,26 @@ impl ResponseError for Error {
             Error::NotFound(_) => StatusCode::NOT_FOUND,
             Error::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Error::DuplicateQuestion { .. } => StatusCode::CONFLICT,
+            Error::InvalidImport { .. } => StatusCode::UNPROCESSABLE_ENTITY,
             Error::Wither(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Error::Mongo(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
     fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
-        let status_code = self.status_code();
+        let error_message = ErrorResponse::from(self);
 
-        let error_message = ErrorResponse {
-            status: status_code.into(),
-            error: self.to_string(),
-        };
+        let mut res = HttpResponse::build(self.status_code());
+        if let Some(retry_after) = error_message.retry_after {
+            res.insert_header((header::RETRY_AFTER, retry_after));
+        }
 
-        HttpResponse::build(status_code).json(error_message)
+        res.json(error_message)
     }
 }
 
@@ -60,7 +125,31 @@ impl ResponseError for Error {
 pub struct BadRequest {}
 
 #[derive(Serialize, Debug)]
-struct ErrorResponse {
+pub struct ErrorResponse {
     status: u16,
     error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggestions: Option<Vec<SimilarQuestion>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rows: Option<Vec<RowError>>,
+}
+
+impl From<&Error> for ErrorResponse {
+    fn from(error: &Error) -> Self {
+        Self {
+            status: error.status_code().into(),
+            error: error.to_string(),
+            retry_after: error.retry_after_secs(),
+            suggestions: match error {
+                Error::DuplicateQuestion { suggestions } => Some(suggestions.clone()),
+                _ => None,
+            },
+            rows: match error {
+                Error::InvalidImport { rows } => Some(rows.clone()),
+                _ => None,
+            },
+        }
+    }
 }
@@ -1,19 +1,32 @@
-use std::{
-    pin::pin,
-    time::{Duration, Instant},
-};
+use std::{collections::HashSet, pin::pin, sync::Arc, time::Instant};
 
 use actix_ws::{AggregatedMessage, ProtocolError};
 use futures_util::{
     future::{select, Either},
     StreamExt as _,
 };
+use serde::Deserialize;
 use tokio::{sync::mpsc, time::interval};
+use tracing::{field, Span};
+use uuid::Uuid;
 
-use crate::server::RoomServerHandle;
-
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+use crate::{
+    errors::{Error, ErrorResponse},
+    metrics,
+    models::event::{Actor, Source},
+    rate_limit::RateLimiter,
+    reaction_buffer::ReactionBuffer,
+    server::{self, RoomServerHandle},
+    services::{
+        announcement,
+        question::{self as services, NewQuestion},
+        snapshot,
+    },
+    settings,
+    utils::{
+        host::Host, message_data::MessageData, participant::Participant, to_object_id::to_object_id,
+    },
+};
 
 enum MessageSource {
     Client(AggregatedMessage),
@@ -23,43 +36,305 @@ enum MessageSource {
     StreamEnd,
 }
 
+/// Commands a client can send over the socket instead of calling the HTTP routes.
+///
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", content = "data")]
+enum ClientCommand {
+    /// Asks in `room_id`, which defaults to the room of the URL.
+    CreateQuestion {
+        value: String,
+        #[serde(default)]
+        display_name: Option<String>,
+        #[serde(default)]
+        force: bool,
+        #[serde(default)]
+        room_id: Option<String>,
+    },
+    /// Toggles `kind` for the participant, or adds the room's first reaction without it.
+    React {
+        question_id: String,
+        #[serde(default)]
+        kind: Option<String>,
+    },
+    /// Toggles the participant's downvote, in rooms that allow them.
+    Downvote { question_id: String },
+    /// Starts following a room, on sessions opened without one.
+    Subscribe { room_id: String },
+    /// Stops following a room, on sessions opened without one.
+    Unsubscribe { room_id: String },
+    /// Sends a message to everyone in `room_id`, which defaults to the room of the URL, on
+    /// sessions opened with the host token.
+    Announce {
+        body: String,
+        #[serde(default)]
+        room_id: Option<String>,
+    },
+}
+
+/// The rooms a session follows: the one of its URL, or those it subscribed to.
+///
+struct Subscriptions {
+    session_id: Uuid,
+    conn_tx: mpsc::UnboundedSender<String>,
+    participant_id: Option<String>,
+    rooms: HashSet<String>,
+    multiplexed: bool,
+}
+
+impl Subscriptions {
+    /// The room a command is about, `room_id` when given or else the room of the URL.
+    ///
+    fn target(&self, room_id: Option<String>) -> Result<String, Error> {
+        match room_id {
+            Some(room_id) => Ok(room_id),
+            None if !self.multiplexed => Ok(self.rooms.iter().next().cloned().unwrap_or_default()),
+            None => Err(Error::bad_request(
+                "A room_id is required on sessions following several rooms".into(),
+            )),
+        }
+    }
+
+    async fn subscribe(
+        &mut self,
+        room_server: &RoomServerHandle,
+        room_id: String,
+    ) -> Result<String, Error> {
+        if !self.multiplexed {
+            return Err(Error::bad_request(
+                "Only sessions opened with /rooms/subscribe can follow other rooms".into(),
+            ));
+        }
+
+        let max = settings::get().websocket.max_subscriptions;
+        if !self.rooms.contains(&room_id) && self.rooms.len() >= max {
+            return Err(Error::bad_request(format!(
+                "A session can follow at most {max} rooms"
+            )));
+        }
+
+        to_object_id(&room_id)
+            .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+        // joined before the snapshot is read, so no change falls between the two
+        let subscribed = self.rooms.insert(room_id.clone());
+        let joined = room_server
+            .subscribe(
+                self.session_id,
+                self.conn_tx.clone(),
+                room_id.clone(),
+                self.participant_id.clone(),
+            )
+            .await;
+
+        match snapshot::load(&room_id, joined).await {
+            Ok(snapshot) => {
+                if subscribed {
+                    tracing::info!(room_id, "session subscribed");
+                }
+                let msg = String::from(MessageData::snapshot(&snapshot));
+                Ok(server::tag(&room_id, &msg))
+            }
+            Err(err) => {
+                if subscribed {
+                    self.rooms.remove(&room_id);
+                    room_server.unsubscribe(self.session_id, room_id);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn unsubscribe(
+        &mut self,
+        room_server: &RoomServerHandle,
+        room_id: String,
+    ) -> Result<String, Error> {
+        if !self.multiplexed {
+            return Err(Error::bad_request(
+                "Only sessions opened with /rooms/subscribe can follow other rooms".into(),
+            ));
+        }
+
+        if self.rooms.remove(&room_id) {
+            room_server.unsubscribe(self.session_id, room_id.clone());
+            tracing::info!(room_id, "session unsubscribed");
+        }
+
+        let msg = String::from(MessageData::unsubscribed(&()));
+        Ok(server::tag(&room_id, &msg))
+    }
+}
+
+/// Run a command from the client, returning what to answer on this session only.
+///
+async fn handle_command(
+    room_server: &RoomServerHandle,
+    rate_limiter: &RateLimiter,
+    reaction_buffer: &ReactionBuffer,
+    participant: &Participant,
+    host: Option<Host>,
+    subscriptions: &mut Subscriptions,
+    text: &str,
+) -> Result<Option<String>, Error> {
+    let command: ClientCommand = serde_json::from_str(text)
+        .map_err(|err| Error::BadRequest(format!("Invalid command: {err}")))?;
+
+    match command {
+        ClientCommand::CreateQuestion {
+            value,
+            display_name,
+            force,
+            room_id,
+        } => {
+            services::create_question(
+                room_server,
+                rate_limiter,
+                participant,
+                Source::Ws,
+                &subscriptions.target(room_id)?,
+                NewQuestion {
+                    value,
+                    display_name,
+                    force,
+                },
+            )
+            .await?;
+        }
+        ClientCommand::React {
+            question_id,
+            kind: Some(kind),
+        } => {
+            services::toggle_reaction(
+                room_server,
+                rate_limiter,
+                participant,
+                Source::Ws,
+                &question_id,
+                &kind,
+            )
+            .await?;
+        }
+        ClientCommand::React {
+            question_id,
+            kind: None,
+        } => {
+            services::react_question(
+                room_server,
+                rate_limiter,
+                reaction_buffer,
+                participant,
+                Source::Ws,
+                &question_id,
+            )
+            .await?;
+        }
+        ClientCommand::Downvote { question_id } => {
+            services::toggle_downvote(
+                room_server,
+                rate_limiter,
+                participant,
+                Source::Ws,
+                &question_id,
+            )
+            .await?;
+        }
+        ClientCommand::Subscribe { room_id } => {
+            return subscriptions
+                .subscribe(room_server, room_id)
+                .await
+                .map(Some);
+        }
+        ClientCommand::Unsubscribe { room_id } => {
+            return subscriptions.unsubscribe(room_server, room_id).map(Some);
+        }
+        ClientCommand::Announce { body, room_id } => {
+            if host.is_none() {
+                return Err(Error::Unauthorized("Invalid or missing host token".into()));
+            }
+
+            let actor = Actor::new(participant, Source::Ws);
+            announcement::announce(room_server, &actor, &subscriptions.target(room_id)?, body)
+                .await?;
+        }
+    }
+
+    Ok(None)
+}
+
+/// Serve a websocket session, following `room_id` or, without one, the rooms the client
+/// subscribes to.
 pub async fn room_subscribe_handle(
     room_server: RoomServerHandle,
-    mut session: actix_ws::Session,
-    msg_stream: actix_ws::MessageStream,
-    room_id: String,
+    rate_limiter: Arc<RateLimiter>,
+    reaction_buffer: ReactionBuffer,
+    participant: Participant,
+    host: Option<Host>,
+    (mut session, msg_stream): (actix_ws::Session, actix_ws::MessageStream),
+    room_id: Option<String>,
 ) {
-    log::info!("connected");
+    let settings = &settings::get().websocket;
 
     let mut last_heartbeat = Instant::now();
-    let mut interval = interval(HEARTBEAT_INTERVAL);
+    let mut interval = interval(settings.heartbeat_interval());
 
     let (conn_tx, mut conn_rx) = mpsc::unbounded_channel();
 
-    // unwrap: room server is not dropped before the HTTP server
-    let session_id = room_server.connect(conn_tx, room_id.to_owned()).await;
+    let (session_id, joined) = match &room_id {
+        Some(room_id) => {
+            let (session_id, joined) = room_server
+                .connect(conn_tx.clone(), room_id.clone(), participant.id.clone())
+                .await;
+            (session_id, Some((room_id.clone(), joined)))
+        }
+        None => (room_server.open(), None),
+    };
+    Span::current().record("session_id", field::display(session_id));
+    tracing::info!("session connected");
+
+    // the room's messages are queued from the moment it was joined and only read after this
+    if let Some((room_id, joined)) = joined {
+        let msg: String = match snapshot::load(&room_id, joined).await {
+            Ok(snapshot) => MessageData::snapshot(&snapshot).into(),
+            Err(err) => MessageData::error(&ErrorResponse::from(&err)).into(),
+        };
+        let _ = session.text(msg).await;
+    }
+
+    let mut subscriptions = Subscriptions {
+        session_id,
+        conn_tx,
+        participant_id: participant.id.clone(),
+        multiplexed: room_id.is_none(),
+        rooms: room_id.into_iter().collect(),
+    };
 
     let msg_stream = msg_stream
-        .max_frame_size(128 * 1024)
+        .max_frame_size(settings.max_frame_size)
         .aggregate_continuations()
-        .max_continuation_size(2 * 1024 * 1024);
+        .max_continuation_size(settings.max_continuation_size);
 
     let mut msg_stream = pin!(msg_stream);
 
     let close_reason = loop {
-        // most of the futures we process need to be stack-pinned to work with select()
-        let tick = pin!(interval.tick());
-        let msg_rx = pin!(conn_rx.recv());
-
-        let messages = pin!(select(msg_stream.next(), msg_rx));
-
-        let message_source = match select(messages, tick).await {
-            Either::Left((Either::Left((Some(Ok(msg)), _)), _)) => MessageSource::Client(msg),
-            Either::Left((Either::Left((Some(Err(err)), _)), _)) => MessageSource::ClientError(err),
-            Either::Left((Either::Left((None, _)), _)) => MessageSource::StreamEnd,
-            Either::Left((Either::Right((Some(msg), _)), _)) => MessageSource::Participant(msg),
-            Either::Left((Either::Right((None, _)), _)) => unreachable!(),
-            Either::Right((_inst, _)) => MessageSource::Heartbeat,
+        // scoped so the pinned futures release their borrows before the message is handled
+        let message_source = {
+            // most of the futures we process need to be stack-pinned to work with select()
+            let tick = pin!(interval.tick());
+            let msg_rx = pin!(conn_rx.recv());
+
+            let messages = pin!(select(msg_stream.next(), msg_rx));
+
+            match select(messages, tick).await {
+                Either::Left((Either::Left((Some(Ok(msg)), _)), _)) => MessageSource::Client(msg),
+                Either::Left((Either::Left((Some(Err(err)), _)), _)) => {
+                    MessageSource::ClientError(err)
+                }
+                Either::Left((Either::Left((None, _)), _)) => MessageSource::StreamEnd,
+                Either::Left((Either::Right((Some(msg), _)), _)) => MessageSource::Participant(msg),
+                Either::Left((Either::Right((None, _)), _)) => unreachable!(),
+                Either::Right((_inst, _)) => MessageSource::Heartbeat,
+            }
         };
 
         match message_source {
@@ -73,33 +348,55 @@ pub async fn room_subscribe_handle(
                     AggregatedMessage::Pong(_) => {
                         last_heartbeat = Instant::now();
                     }
-                    AggregatedMessage::Text(_text) => {
-                        // message sent by the client
+                    AggregatedMessage::Text(text) => {
+                        // results reach everyone through the room broadcast, only failures and
+                        // subscriptions are answered on this session
+                        let reply = handle_command(
+                            &room_server,
+                            &rate_limiter,
+                            &reaction_buffer,
+                            &participant,
+                            host,
+                            &mut subscriptions,
+                            &text,
+                        )
+                        .await
+                        .unwrap_or_else(|err| {
+                            let response = ErrorResponse::from(&err);
+                            Some(MessageData::error(&response).into())
+                        });
+                        if let Some(msg) = reply {
+                            let _ = session.text(msg).await;
+                        }
                     }
                     AggregatedMessage::Binary(_bin) => {
-                        log::warn!("unexpected binary message");
+                        tracing::warn!("unexpected binary message");
                     }
                     AggregatedMessage::Close(reason) => break reason,
                 }
             }
             MessageSource::Participant(text) => {
+                metrics::SESSION_QUEUE_DEPTH.observe(conn_rx.len() as f64);
                 session.text(text).await.unwrap();
             }
             MessageSource::Heartbeat => {
-                if Instant::now().duration_since(last_heartbeat) > CLIENT_TIMEOUT {
+                if Instant::now().duration_since(last_heartbeat) > settings.client_timeout() {
+                    metrics::HEARTBEAT_TIMEOUTS.inc();
+                    tracing::info!("client heartbeat timed out");
                     break None;
                 }
                 let _ = session.ping(b"").await;
             }
             MessageSource::ClientError(err) => {
-                log::error!("{}", err);
+                tracing::error!(error = %err, "websocket protocol error");
                 break None;
             }
             MessageSource::StreamEnd => break None,
         }
     };
 
-    room_server.disconnect(session_id);
+    room_server.disconnect(session_id, subscriptions.rooms.iter().map(String::as_str));
+    tracing::info!("session closed");
 
     let _ = session.close(close_reason).await;
 }
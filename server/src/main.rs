@@ -1,53 +1,164 @@
-use crate::{routes::question, routes::room};
+use crate::{
+    routes::event, routes::health, routes::metrics as metrics_routes, routes::poll,
+    routes::question, routes::room, routes::webhook,
+};
 use actix_cors::Cors;
-use actix_web::{middleware, web};
+use actix_web::{http::header, middleware, web};
 use actix_web::{web::JsonConfig, App, HttpResponse, HttpServer};
+use cli::Args;
+use rate_limit::RateLimiter;
+use reaction_buffer::ReactionBuffer;
 use serde_json::json;
 use server::RoomServer;
+use settings::Settings;
 use tokio::{spawn, try_join};
+use tracing_actix_web::TracingLogger;
 
+mod change_streams;
+mod cli;
 mod database;
 mod errors;
 mod handler;
+mod metrics;
 mod models;
+mod rate_limit;
+mod reaction_buffer;
 mod routes;
 mod server;
+mod services;
+mod settings;
+mod telemetry;
 mod utils;
+mod webhooks;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+    dotenv::dotenv().ok();
 
-    let json_config = JsonConfig::default().error_handler(|err, _req| {
-        let error_response = json!({ "message": err.to_string() });
+    let args = Args::parse()?;
+    let settings = Settings::load(args.config_path.as_deref())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
 
-        actix_web::error::InternalError::from_response(
-            err,
-            HttpResponse::BadRequest().json(error_response),
-        )
-        .into()
+    if args.print_config {
+        let output = toml::to_string_pretty(&settings).map_err(std::io::Error::other)?;
+        print!("{output}");
+        return Ok(());
+    }
+
+    settings::init(settings);
+    let settings = settings::get();
+
+    let _telemetry = telemetry::init(&settings.telemetry)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+
+    metrics::init();
+
+    if let Some(import) = &args.import {
+        return cli::run_import(import).await;
+    }
+
+    if let Some(room_id) = &args.rebuild {
+        return cli::run_rebuild(room_id).await;
+    }
+
+    // in the background so the server still starts, and reports not ready, while MongoDB is down
+    spawn(async {
+        if let Err(err) = database::sync_indexes().await {
+            tracing::error!(error = %err, "failed to sync MongoDB indexes");
+        }
+    });
+
+    spawn(async {
+        if let Err(err) = webhooks::resume().await {
+            tracing::error!(error = %err, "failed to resume webhook deliveries");
+        }
     });
 
-    let (room_server, server_tx) = RoomServer::new();
+    let json_config = JsonConfig::default()
+        .limit(settings.server.json_limit)
+        .error_handler(|err, _req| {
+            let error_response = json!({ "message": err.to_string() });
+
+            actix_web::error::InternalError::from_response(
+                err,
+                HttpResponse::BadRequest().json(error_response),
+            )
+            .into()
+        });
+
+    let (room_server, server_tx) = RoomServer::new(
+        settings.websocket.room_shards,
+        settings.reactions.broadcast_window(),
+    );
     let room_server = spawn(room_server.run());
 
-    let http_server = HttpServer::new(move || {
-        let cors = Cors::default()
-            .allowed_origin("http://localhost:5173")
+    let watcher = settings
+        .change_streams
+        .enabled
+        .then(|| spawn(change_streams::watch(server_tx.clone())));
+
+    let reaction_buffer = ReactionBuffer::new(settings.reactions.buffered);
+    let flusher = reaction_buffer
+        .enabled()
+        .then(|| spawn(reaction_buffer.clone().run(server_tx.clone())));
+    let buffer = web::Data::new(reaction_buffer.clone());
+
+    // shared across workers, otherwise each one would keep its own budget
+    let rate_limiter = web::Data::new(RateLimiter::new(settings.rate_limit.clone()));
+
+    let mut http_server = HttpServer::new(move || {
+        let cors = settings
+            .cors
+            .allowed_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| match origin.as_str() {
+                "*" => cors.allow_any_origin(),
+                origin => cors.allowed_origin(origin),
+            })
             .allow_any_header()
             .allow_any_method()
-            .max_age(3600);
+            .expose_headers([header::RETRY_AFTER])
+            .max_age(settings.cors.max_age_secs);
 
         App::new()
             .wrap(cors)
             .app_data(web::Data::new(server_tx.clone()))
+            .app_data(rate_limiter.clone())
+            .app_data(buffer.clone())
             .app_data(json_config.clone())
+            .app_data(web::PayloadConfig::new(settings.server.json_limit))
+            .configure(health::create_routes)
+            .configure(metrics_routes::create_routes)
             .configure(room::create_routes)
             .configure(question::create_routes)
-            .wrap(middleware::Logger::default())
-    })
-    .bind(("127.0.0.1", 8080))?
-    .run();
+            .configure(poll::create_routes)
+            .configure(webhook::create_routes)
+            .configure(event::create_routes)
+            .wrap(middleware::from_fn(metrics::track_requests))
+            .wrap(TracingLogger::default())
+    });
+
+    for addr in &settings.server.listen {
+        http_server = http_server.bind(addr)?;
+    }
+
+    let http_server = async move {
+        http_server.run().await?;
+
+        // the watcher never stops on its own, and the room server runs until it lets go of it
+        if let Some(watcher) = watcher {
+            watcher.abort();
+            let _ = watcher.await;
+        }
+
+        // the HTTP server stopped gracefully, write what is left before the process exits
+        if let Some(flusher) = flusher {
+            reaction_buffer.stop();
+            let _ = flusher.await;
+        }
+
+        Ok(())
+    };
 
     try_join!(http_server, async move { room_server.await.unwrap() })?;
 
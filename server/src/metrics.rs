@@ -0,0 +1,181 @@
+use std::{future::Future, sync::LazyLock, time::Instant};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    Error as ActixWebError,
+};
+use prometheus::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge, Encoder, Histogram, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+
+pub static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "HTTP request latency by route.",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+pub static ACTIVE_SESSIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "room_server_active_sessions",
+        "Websocket sessions registered in the room server."
+    )
+    .unwrap()
+});
+
+pub static ACTIVE_ROOMS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "room_server_active_rooms",
+        "Rooms with at least one subscribed session."
+    )
+    .unwrap()
+});
+
+pub static BROADCAST_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "room_server_broadcast_duration_seconds",
+        "Time spent fanning a message out to every session of a room.",
+        exponential_buckets(0.00001, 4.0, 10).unwrap()
+    )
+    .unwrap()
+});
+
+pub static BROADCAST_RECIPIENTS: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "room_server_broadcast_recipients",
+        "Sessions reached by a single broadcast.",
+        exponential_buckets(1.0, 4.0, 8).unwrap()
+    )
+    .unwrap()
+});
+
+pub static COALESCED_MESSAGES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "room_server_coalesced_messages_total",
+        "Messages replaced by a newer one for the same key before the broadcast window ended."
+    )
+    .unwrap()
+});
+
+pub static SESSION_QUEUE_DEPTH: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "ws_session_queue_depth",
+        "Messages still waiting in a session queue when one is written to the socket.",
+        exponential_buckets(1.0, 2.0, 12).unwrap()
+    )
+    .unwrap()
+});
+
+pub static HEARTBEAT_TIMEOUTS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "ws_heartbeat_timeouts_total",
+        "Websocket sessions closed because the client stopped answering pings."
+    )
+    .unwrap()
+});
+
+pub static DB_OPERATION_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "db_operation_duration_seconds",
+        "MongoDB operation latency.",
+        &["collection", "operation"]
+    )
+    .unwrap()
+});
+
+pub static DB_OPERATION_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "db_operation_errors_total",
+        "MongoDB operations that returned an error.",
+        &["collection", "operation"]
+    )
+    .unwrap()
+});
+
+/// Register every metric up front so `/metrics` lists them before they are first touched.
+///
+pub fn init() {
+    LazyLock::force(&HTTP_REQUEST_DURATION);
+    LazyLock::force(&ACTIVE_SESSIONS);
+    LazyLock::force(&ACTIVE_ROOMS);
+    LazyLock::force(&BROADCAST_DURATION);
+    LazyLock::force(&BROADCAST_RECIPIENTS);
+    LazyLock::force(&COALESCED_MESSAGES);
+    LazyLock::force(&SESSION_QUEUE_DEPTH);
+    LazyLock::force(&HEARTBEAT_TIMEOUTS);
+    LazyLock::force(&DB_OPERATION_DURATION);
+    LazyLock::force(&DB_OPERATION_ERRORS);
+}
+
+/// Render the default registry in the Prometheus text format.
+///
+pub fn encode() -> Result<String, prometheus::Error> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/// Middleware recording the latency of every request, labelled by the matched route pattern.
+///
+/// It also serves as the access log, running inside the request span so each line carries
+/// its request id.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, ActixWebError> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    // patterns keep the label cardinality bounded, unlike raw paths with ids in them
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".into());
+
+    let res = next.call(req).await;
+
+    let status = match &res {
+        Ok(res) => res.status(),
+        Err(err) => err.as_response_error().status_code(),
+    };
+
+    let elapsed = started.elapsed();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[&method, &route, status.as_str()])
+        .observe(elapsed.as_secs_f64());
+
+    tracing::info!(
+        %method,
+        %route,
+        status = status.as_u16(),
+        latency_ms = elapsed.as_millis() as u64,
+        "request completed"
+    );
+
+    res
+}
+
+/// Time a database operation and count it as failed if it returns an error.
+///
+pub async fn time_db<F, T, E>(collection: &str, operation: &str, fut: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let started = Instant::now();
+    let result = fut.await;
+
+    DB_OPERATION_DURATION
+        .with_label_values(&[collection, operation])
+        .observe(started.elapsed().as_secs_f64());
+
+    if result.is_err() {
+        DB_OPERATION_ERRORS
+            .with_label_values(&[collection, operation])
+            .inc();
+    }
+
+    result
+}
@@ -0,0 +1,149 @@
+use bson::serde_helpers::{bson_datetime_as_rfc3339_string, serialize_object_id_as_hex_string};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use wither::bson::{doc, oid::ObjectId, Bson, DateTime};
+use wither::Model as WitherModel;
+
+use crate::utils::{models::ModelExt, participant::Participant};
+
+impl ModelExt for Event {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventKind {
+    #[serde(rename = "room.created")]
+    RoomCreated,
+    #[serde(rename = "room.closed")]
+    RoomClosed,
+    /// `question_id` is the question put in the spotlight, `None` when it was cleared.
+    #[serde(rename = "room.spotlighted")]
+    RoomSpotlighted,
+    /// A message of the hosts to everyone in the room.
+    #[serde(rename = "room.announced")]
+    RoomAnnounced,
+    #[serde(rename = "question.created")]
+    QuestionCreated,
+    #[serde(rename = "question.answered")]
+    QuestionAnswered,
+    #[serde(rename = "question.unanswered")]
+    QuestionUnanswered,
+    #[serde(rename = "question.reacted")]
+    QuestionReacted,
+    #[serde(rename = "question.downvoted")]
+    QuestionDownvoted,
+    /// Recorded for both questions of a merge: the duplicate, which is removed, and the
+    /// question it was merged into.
+    #[serde(rename = "question.merged")]
+    QuestionMerged,
+    #[serde(rename = "answer.created")]
+    AnswerCreated,
+    #[serde(rename = "answer.updated")]
+    AnswerUpdated,
+    #[serde(rename = "answer.deleted")]
+    AnswerDeleted,
+    #[serde(rename = "reply.created")]
+    ReplyCreated,
+    #[serde(rename = "reply.updated")]
+    ReplyUpdated,
+    #[serde(rename = "reply.deleted")]
+    ReplyDeleted,
+    #[serde(rename = "poll.created")]
+    PollCreated,
+    #[serde(rename = "poll.opened")]
+    PollOpened,
+    #[serde(rename = "poll.closed")]
+    PollClosed,
+    #[serde(rename = "poll.voted")]
+    PollVoted,
+}
+
+/// Where a mutation came from.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Http,
+    Ws,
+    Cli,
+    /// The server on its own, e.g. when writing buffered reactions.
+    Server,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Actor {
+    pub participant_id: Option<String>,
+    pub ip: Option<String>,
+    pub source: Source,
+}
+
+impl Actor {
+    pub fn new(participant: &Participant, source: Source) -> Self {
+        Self {
+            participant_id: participant.id.clone(),
+            ip: participant.ip.map(|ip| ip.to_string()),
+            source,
+        }
+    }
+
+    pub fn cli() -> Self {
+        Self {
+            participant_id: None,
+            ip: None,
+            source: Source::Cli,
+        }
+    }
+
+    pub fn server() -> Self {
+        Self {
+            participant_id: None,
+            ip: None,
+            source: Source::Server,
+        }
+    }
+}
+
+/// An entry of the append-only log of room and question mutations.
+///
+/// `before` and `after` hold the whole document, `None` when it did not exist yet or anymore.
+#[derive(Debug, Clone, Serialize, Deserialize, WitherModel, Validate)]
+#[model(index(keys = r#"doc!{"room_id": 1, "_id": -1}"#))]
+pub struct Event {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub room_id: ObjectId,
+    pub question_id: Option<ObjectId>,
+    pub kind: EventKind,
+    pub actor: Actor,
+    pub before: Option<Bson>,
+    pub after: Option<Bson>,
+    pub created_at: DateTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicEvent {
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    pub id: ObjectId,
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    pub room_id: ObjectId,
+    pub question_id: Option<String>,
+    pub kind: EventKind,
+    pub actor: Actor,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    #[serde(with = "bson_datetime_as_rfc3339_string")]
+    pub created_at: DateTime,
+}
+
+impl From<Event> for PublicEvent {
+    fn from(event: Event) -> Self {
+        Self {
+            id: event.id.unwrap(),
+            room_id: event.room_id,
+            question_id: event.question_id.map(|id| id.to_hex()),
+            kind: event.kind,
+            actor: event.actor,
+            before: event.before.map(Bson::into_relaxed_extjson),
+            after: event.after.map(Bson::into_relaxed_extjson),
+            created_at: event.created_at,
+        }
+    }
+}
@@ -1,2 +1,5 @@
 pub mod room;
 pub mod question;
+pub mod event;
+pub mod poll;
+pub mod webhook;
@@ -0,0 +1,107 @@
+use bson::serde_helpers::{bson_datetime_as_rfc3339_string, serialize_object_id_as_hex_string};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use wither::bson::{doc, oid::ObjectId, DateTime};
+use wither::Model as WitherModel;
+
+use crate::utils::models::ModelExt;
+
+impl ModelExt for Poll {}
+impl ModelExt for PollVote {}
+
+#[derive(Debug, Clone, Serialize, Deserialize, WitherModel, Validate)]
+#[model(index(keys = r#"doc!{"room_id": 1}"#))]
+pub struct Poll {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub room_id: ObjectId,
+    #[validate(length(
+        min = 1,
+        max = 300,
+        message = "Poll question must be 1 to 300 characters"
+    ))]
+    pub question: String,
+    #[validate(
+        length(min = 2, max = 10, message = "A poll needs 2 to 10 options"),
+        nested
+    )]
+    pub options: Vec<PollOption>,
+    /// Participants may pick several options instead of exactly one.
+    pub multiple: bool,
+    pub open: bool,
+    /// Participants who voted, options of multiple-choice polls can add up to more.
+    pub voters: u32,
+}
+
+impl Poll {
+    pub fn new(room_id: ObjectId, question: String, options: Vec<String>, multiple: bool) -> Self {
+        Self {
+            id: None,
+            room_id,
+            question,
+            options: options
+                .into_iter()
+                .map(|text| PollOption { text, votes: 0 })
+                .collect(),
+            multiple,
+            open: true,
+            voters: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct PollOption {
+    #[validate(length(
+        min = 1,
+        max = 100,
+        message = "Poll options must be 1 to 100 characters"
+    ))]
+    pub text: String,
+    pub votes: u32,
+}
+
+/// The choices of one participant, the unique index allows a single vote per poll.
+///
+#[derive(Debug, Clone, Serialize, Deserialize, WitherModel, Validate)]
+#[model(index(
+    keys = r#"doc!{"poll_id": 1, "participant_id": 1}"#,
+    options = r#"doc!{"unique": true}"#
+))]
+pub struct PollVote {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub poll_id: ObjectId,
+    pub participant_id: String,
+    /// Positions of the chosen options.
+    pub choices: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicPoll {
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    pub id: ObjectId,
+    #[serde(with = "bson_datetime_as_rfc3339_string")]
+    pub created_at: DateTime,
+    pub question: String,
+    pub options: Vec<PollOption>,
+    pub multiple: bool,
+    pub open: bool,
+    pub voters: u32,
+}
+
+impl From<Poll> for PublicPoll {
+    fn from(poll: Poll) -> Self {
+        let id = poll.id.unwrap();
+
+        Self {
+            id,
+            created_at: id.timestamp(),
+            question: poll.question,
+            options: poll.options,
+            multiple: poll.multiple,
+            open: poll.open,
+            voters: poll.voters,
+        }
+    }
+}
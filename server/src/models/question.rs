@@ -1,21 +1,64 @@
-use bson::serde_helpers::serialize_object_id_as_hex_string;
+use std::collections::BTreeMap;
+
+use bson::serde_helpers::{bson_datetime_as_rfc3339_string, serialize_object_id_as_hex_string};
 use serde::{Deserialize, Serialize};
 use validator::Validate;
-use wither::bson::{doc, oid::ObjectId};
+use wither::bson::{doc, oid::ObjectId, DateTime};
 use wither::Model as WitherModel;
 
 use crate::utils::models::ModelExt;
 
 impl ModelExt for Question {}
+impl ModelExt for QuestionReaction {}
+impl ModelExt for QuestionDownvote {}
 
 #[derive(Debug, Clone, Serialize, Deserialize, WitherModel, Validate)]
+#[model(index(keys = r#"doc!{"value": "text"}"#))]
+#[model(index(
+    keys = r#"doc!{"room_id": 1, "import_key": 1}"#,
+    options = r#"doc!{"unique": true, "partialFilterExpression": {"import_key": {"$exists": true}}}"#
+))]
+#[model(index(keys = r#"doc!{"room_id": 1, "participant_id": 1}"#))]
 pub struct Question {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     pub room_id: ObjectId,
     pub answered: bool,
+    /// Total of all reactions, what the `top` sort orders by.
     pub reaction_count: u16,
+    /// Count of each reaction kind. Reactions added before kinds existed are only in the total.
+    #[serde(default)]
+    pub reactions: BTreeMap<String, u16>,
+    #[serde(default)]
+    pub downvotes: u16,
+    /// Lower bound of the share of upvotes, what rooms with downvotes rank by.
+    #[serde(default)]
+    pub confidence: f64,
+    /// Set once the score falls below the room's `hide_below`.
+    #[serde(default)]
+    pub hidden: bool,
+    #[validate(length(min = 1, message = "Question cannot be empty"))]
     pub value: String,
+    /// Name the participant signed the question with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// Asked in a room whose names are anonymous, `display_name` is then only revealed to
+    /// hosts.
+    #[serde(default)]
+    pub anonymous: bool,
+    /// Who asked, only revealed to hosts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub participant_id: Option<String>,
+    /// Identifies questions created by an import, so running it again skips them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub import_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answer: Option<Answer>,
+    #[serde(default)]
+    pub replies: Vec<Reply>,
+    /// Reaction counts `question.reaction_milestone` was already sent for.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reaction_milestones: Vec<u16>,
 }
 
 impl Question {
@@ -25,7 +68,147 @@ impl Question {
             room_id,
             answered: false,
             reaction_count: 0,
+            reactions: BTreeMap::new(),
+            downvotes: 0,
+            confidence: 0.0,
+            hidden: false,
             value,
+            display_name: None,
+            anonymous: false,
+            participant_id: None,
+            import_key: None,
+            answer: None,
+            replies: Vec::new(),
+            reaction_milestones: Vec::new(),
+        }
+    }
+
+    /// The participants a change to the question concerns, its author when known.
+    pub fn involves(&self) -> Vec<String> {
+        self.participant_id.iter().cloned().collect()
+    }
+
+    /// Reactions minus downvotes.
+    pub fn score(&self) -> i32 {
+        i32::from(self.reaction_count) - i32::from(self.downvotes)
+    }
+
+    /// Update the confidence to the current counts and hide the question when its score is
+    /// below `hide_below`.
+    pub fn rescore(&mut self, hide_below: Option<i32>) {
+        self.confidence = wilson_lower_bound(self.reaction_count, self.downvotes);
+        self.hidden = hide_below.is_some_and(|threshold| self.score() < threshold);
+    }
+}
+
+/// Lower bound of the Wilson score interval at 95% confidence, so a question with few votes
+/// does not outrank one with many mostly positive ones.
+fn wilson_lower_bound(up: u16, down: u16) -> f64 {
+    const Z: f64 = 1.96;
+
+    let n = f64::from(up) + f64::from(down);
+    if n == 0.0 {
+        return 0.0;
+    }
+    let p = f64::from(up) / n;
+    let z2 = Z * Z;
+
+    (p + z2 / (2.0 * n) - Z * ((p * (1.0 - p) + z2 / (4.0 * n)) / n).sqrt()) / (1.0 + z2 / n)
+}
+
+/// A reaction of one participant, the unique index allows each kind once per question.
+///
+#[derive(Debug, Clone, Serialize, Deserialize, WitherModel, Validate)]
+#[model(index(
+    keys = r#"doc!{"question_id": 1, "participant_id": 1, "kind": 1}"#,
+    options = r#"doc!{"unique": true}"#
+))]
+#[model(index(keys = r#"doc!{"participant_id": 1}"#))]
+pub struct QuestionReaction {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub question_id: ObjectId,
+    pub participant_id: String,
+    pub kind: String,
+}
+
+/// A downvote of one participant, who can only cast one per question.
+///
+#[derive(Debug, Clone, Serialize, Deserialize, WitherModel, Validate)]
+#[model(index(
+    keys = r#"doc!{"question_id": 1, "participant_id": 1}"#,
+    options = r#"doc!{"unique": true}"#
+))]
+#[model(index(keys = r#"doc!{"participant_id": 1}"#))]
+pub struct QuestionDownvote {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub question_id: ObjectId,
+    pub participant_id: String,
+}
+
+/// A written answer from the host, kept as Markdown along with its sanitized HTML.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Answer {
+    pub body: String,
+    pub html: String,
+    pub updated_at: DateTime,
+}
+
+/// A follow-up from the audience, threaded under a question.
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reply {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// Only the participant who wrote the reply can edit or delete it.
+    pub participant_id: Option<String>,
+    pub body: String,
+    pub html: String,
+    #[serde(default)]
+    pub edited: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicAnswer {
+    pub body: String,
+    pub html: String,
+    #[serde(with = "bson_datetime_as_rfc3339_string")]
+    pub updated_at: DateTime,
+}
+
+impl From<Answer> for PublicAnswer {
+    fn from(answer: Answer) -> Self {
+        Self {
+            body: answer.body,
+            html: answer.html,
+            updated_at: answer.updated_at,
+        }
+    }
+}
+
+/// A reply without its author, whose participant id must stay private.
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicReply {
+    #[serde(alias = "_id", serialize_with = "serialize_object_id_as_hex_string")]
+    pub id: ObjectId,
+    #[serde(with = "bson_datetime_as_rfc3339_string")]
+    pub created_at: DateTime,
+    pub body: String,
+    pub html: String,
+    pub edited: bool,
+}
+
+impl From<Reply> for PublicReply {
+    fn from(reply: Reply) -> Self {
+        Self {
+            id: reply.id,
+            created_at: reply.id.timestamp(),
+            body: reply.body,
+            html: reply.html,
+            edited: reply.edited,
         }
     }
 }
@@ -34,18 +217,151 @@ impl Question {
 pub struct PublicQuestion {
     #[serde(alias = "_id", serialize_with = "serialize_object_id_as_hex_string")]
     pub id: ObjectId,
+    /// Taken from the id, so questions stored before it was exposed have one too.
+    #[serde(with = "bson_datetime_as_rfc3339_string")]
+    pub created_at: DateTime,
     pub answered: bool,
     pub reaction_count: u16,
+    #[serde(default)]
+    pub reactions: BTreeMap<String, u16>,
+    #[serde(default)]
+    pub downvotes: u16,
+    /// Reactions minus downvotes.
+    #[serde(default)]
+    pub score: i32,
+    #[serde(default)]
+    pub hidden: bool,
     pub value: String,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answer: Option<PublicAnswer>,
+    #[serde(default)]
+    pub replies: Vec<PublicReply>,
 }
 
 impl From<Question> for PublicQuestion {
     fn from(question: Question) -> Self {
+        let id = question.id.unwrap();
+
         Self {
-            id: question.id.unwrap(),
+            id,
+            created_at: id.timestamp(),
             answered: question.answered,
             reaction_count: question.reaction_count,
+            score: question.score(),
+            reactions: question.reactions,
+            downvotes: question.downvotes,
+            hidden: question.hidden,
             value: question.value,
+            display_name: question.display_name.filter(|_| !question.anonymous),
+            answer: question.answer.map(Into::into),
+            replies: question.replies.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// Broadcast instead of the whole question when only its reactions or downvotes changed.
+///
+/// It holds every count, so the latest one sent for a question is all a client needs.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReactionCounts {
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    pub question_id: ObjectId,
+    pub reactions: BTreeMap<String, u16>,
+    pub reaction_count: u16,
+    pub downvotes: u16,
+    pub score: i32,
+    pub hidden: bool,
+}
+
+impl ReactionCounts {
+    pub fn new(question: &Question) -> Option<Self> {
+        Some(Self {
+            question_id: question.id?,
+            reactions: question.reactions.clone(),
+            reaction_count: question.reaction_count,
+            downvotes: question.downvotes,
+            score: question.score(),
+            hidden: question.hidden,
+        })
+    }
+
+    /// The counts of `after`, `None` when they are the same as those of `before`.
+    pub fn changed(before: &Question, after: &Question) -> Option<Self> {
+        let unchanged = before.reactions == after.reactions
+            && before.reaction_count == after.reaction_count
+            && before.downvotes == after.downvotes
+            && before.hidden == after.hidden;
+
+        if unchanged {
+            return None;
         }
+
+        Self::new(after)
     }
 }
+
+/// The reaction a participant toggled and whether they have it now.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct ToggledReaction {
+    pub kind: String,
+    pub reacted: bool,
+    pub question: PublicQuestion,
+}
+
+/// Whether the participant has a downvote on the question after toggling it.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct ToggledDownvote {
+    pub downvoted: bool,
+    pub question: PublicQuestion,
+}
+
+/// A question the participant reacted to or downvoted, with the reactions they have on it.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct ParticipantVotes {
+    pub question: PublicQuestion,
+    pub reactions: Vec<String>,
+    pub downvoted: bool,
+}
+
+/// An existing question close to one being asked.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct SimilarQuestion {
+    #[serde(flatten)]
+    pub question: PublicQuestion,
+    pub similarity: f64,
+}
+
+/// Who asked a question, as revealed to hosts for moderation.
+///
+/// `participant_id` and `ip` come from the question's creation in the event log when it has
+/// one, questions asked before authors were stored only have it there.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuestionAuthor {
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    pub question_id: ObjectId,
+    pub display_name: Option<String>,
+    pub participant_id: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// Broadcast when the question being answered changes, `question` is `None` once cleared.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct Spotlight {
+    pub question: Option<PublicQuestion>,
+}
+
+/// Broadcast when a host folds a duplicate into another question.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct MergedQuestion {
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    pub from: ObjectId,
+    pub into: PublicQuestion,
+}
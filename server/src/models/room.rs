@@ -1,28 +1,123 @@
-use bson::serde_helpers::serialize_object_id_as_hex_string;
+use bson::serde_helpers::{bson_datetime_as_rfc3339_string, serialize_object_id_as_hex_string};
 use serde::{Deserialize, Serialize};
 use validator::Validate;
-use wither::bson::{doc, oid::ObjectId};
+use wither::bson::{doc, oid::ObjectId, DateTime, Document};
 use wither::Model as WitherModel;
 
-use crate::utils::models::ModelExt;
+use crate::{settings, utils::models::ModelExt};
 
 impl ModelExt for Room {}
 
-#[derive(Debug, Serialize, Deserialize, WitherModel, Validate)]
+/// Announcements a room keeps, older ones are dropped as new ones come.
+pub const MAX_ANNOUNCEMENTS: usize = 5;
+
+/// Order in which a room lists its questions and picks the next one to spotlight.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortMode {
+    /// Most reactions first, oldest first among equals.
+    Top,
+    /// The order of rooms created before sort modes existed, so it stays theirs.
+    #[default]
+    Newest,
+    Oldest,
+}
+
+impl SortMode {
+    pub fn sort(self) -> Document {
+        match self {
+            SortMode::Top => doc! { "reaction_count": -1, "_id": 1 },
+            SortMode::Newest => doc! { "_id": -1 },
+            SortMode::Oldest => doc! { "_id": 1 },
+        }
+    }
+}
+
+/// Whether participants sign their questions with a display name.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NameMode {
+    #[default]
+    Anonymous,
+    Optional,
+    Required,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, WitherModel, Validate)]
+#[model(index(
+    keys = r#"doc!{"import_key": 1}"#,
+    options = r#"doc!{"unique": true, "partialFilterExpression": {"import_key": {"$exists": true}}}"#
+))]
 pub struct Room {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     #[validate(length(min = 1, message = "Room name cannot be empty"))]
     pub name: String,
     pub questions_count: i8,
+    /// Seconds a participant has to wait between two questions, `0` disables slow mode.
+    #[serde(default)]
+    pub slow_mode_secs: u32,
+    /// Closed rooms keep their questions but accept no new ones.
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub sort_mode: SortMode,
+    /// Participants may downvote questions, which then rank by confidence in their score.
+    #[serde(default)]
+    pub downvotes: bool,
+    /// Questions whose score falls below this are hidden from the room.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hide_below: Option<i32>,
+    /// Reactions participants can add to questions, the first is the one of the legacy
+    /// upvote.
+    #[serde(default = "default_reaction_kinds")]
+    pub reaction_kinds: Vec<String>,
+    #[serde(default)]
+    pub names: NameMode,
+    /// The question being answered right now, shown to everyone in the room.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spotlight: Option<ObjectId>,
+    /// The latest messages of the hosts to everyone in the room, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub announcements: Vec<Announcement>,
+    /// Key of the import that created the room, see [`crate::services::import`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub import_key: Option<String>,
+}
+
+fn default_reaction_kinds() -> Vec<String> {
+    settings::get().reactions.kinds.clone()
 }
 
 impl Room {
-    pub fn new(name: String) -> Self {
+    pub fn new(name: String, slow_mode_secs: u32) -> Self {
         Self {
             id: None,
             name,
             questions_count: 0,
+            slow_mode_secs,
+            closed: false,
+            sort_mode: SortMode::default(),
+            downvotes: false,
+            hide_below: None,
+            reaction_kinds: default_reaction_kinds(),
+            names: NameMode::default(),
+            spotlight: None,
+            announcements: Vec::new(),
+            import_key: None,
+        }
+    }
+
+    /// Order of the questions, rooms with downvotes rank `top` questions by the confidence in
+    /// their score rather than by reactions alone.
+    pub fn question_sort(&self) -> Document {
+        match self.sort_mode {
+            SortMode::Top if self.downvotes => {
+                doc! { "confidence": -1, "reaction_count": -1, "_id": 1 }
+            }
+            sort_mode => sort_mode.sort(),
         }
     }
 }
@@ -33,6 +128,15 @@ pub struct PublicRoom {
     pub id: ObjectId,
     pub name: String,
     pub questions_count: i8,
+    pub slow_mode_secs: u32,
+    pub closed: bool,
+    pub sort_mode: SortMode,
+    pub downvotes: bool,
+    pub hide_below: Option<i32>,
+    pub reaction_kinds: Vec<String>,
+    pub names: NameMode,
+    pub spotlight: Option<String>,
+    pub announcements: Vec<PublicAnnouncement>,
 }
 
 impl From<Room> for PublicRoom {
@@ -41,6 +145,45 @@ impl From<Room> for PublicRoom {
             id: room.id.unwrap(),
             name: room.name,
             questions_count: room.questions_count,
+            slow_mode_secs: room.slow_mode_secs,
+            closed: room.closed,
+            sort_mode: room.sort_mode,
+            downvotes: room.downvotes,
+            hide_below: room.hide_below,
+            reaction_kinds: room.reaction_kinds,
+            names: room.names,
+            spotlight: room.spotlight.map(|id| id.to_hex()),
+            announcements: room
+                .announcements
+                .into_iter()
+                .map(PublicAnnouncement::from)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Announcement {
+    pub id: ObjectId,
+    pub body: String,
+    pub created_at: DateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicAnnouncement {
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    pub id: ObjectId,
+    pub body: String,
+    #[serde(with = "bson_datetime_as_rfc3339_string")]
+    pub created_at: DateTime,
+}
+
+impl From<Announcement> for PublicAnnouncement {
+    fn from(announcement: Announcement) -> Self {
+        Self {
+            id: announcement.id,
+            body: announcement.body,
+            created_at: announcement.created_at,
         }
     }
 }
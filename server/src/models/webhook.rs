@@ -0,0 +1,165 @@
+use bson::serde_helpers::{bson_datetime_as_rfc3339_string, serialize_object_id_as_hex_string};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use wither::bson::{doc, oid::ObjectId, DateTime};
+use wither::Model as WitherModel;
+
+use crate::utils::models::ModelExt;
+
+impl ModelExt for Webhook {}
+impl ModelExt for WebhookDelivery {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "question.created")]
+    QuestionCreated,
+    #[serde(rename = "question.answered")]
+    QuestionAnswered,
+    #[serde(rename = "question.reaction_milestone")]
+    ReactionMilestone,
+    #[serde(rename = "room.closed")]
+    RoomClosed,
+}
+
+impl WebhookEvent {
+    /// Name used in payloads, headers and stored documents.
+    ///
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::QuestionCreated => "question.created",
+            WebhookEvent::QuestionAnswered => "question.answered",
+            WebhookEvent::ReactionMilestone => "question.reaction_milestone",
+            WebhookEvent::RoomClosed => "room.closed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, WitherModel, Validate)]
+#[model(index(keys = r#"doc!{"room_id": 1, "events": 1}"#))]
+pub struct Webhook {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub room_id: ObjectId,
+    #[validate(url(message = "Webhook url must be a valid URL"))]
+    pub url: String,
+    #[validate(length(min = 1, message = "Subscribe to at least one event"))]
+    pub events: Vec<WebhookEvent>,
+    /// Key of the HMAC signature sent with every delivery.
+    pub secret: String,
+}
+
+impl Webhook {
+    pub fn new(room_id: ObjectId, url: String, events: Vec<WebhookEvent>, secret: String) -> Self {
+        Self {
+            id: None,
+            room_id,
+            url,
+            events,
+            secret,
+        }
+    }
+}
+
+/// A webhook without its secret, which is only shown once when the webhook is created.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicWebhook {
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    pub id: ObjectId,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+}
+
+impl From<Webhook> for PublicWebhook {
+    fn from(webhook: Webhook) -> Self {
+        Self {
+            id: webhook.id.unwrap(),
+            url: webhook.url,
+            events: webhook.events,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, WitherModel, Validate)]
+#[model(index(keys = r#"doc!{"webhook_id": 1, "_id": -1}"#))]
+#[model(index(keys = r#"doc!{"status": 1}"#))]
+pub struct WebhookDelivery {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub webhook_id: ObjectId,
+    pub event: WebhookEvent,
+    /// The exact body that was signed and sent.
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub response_status: Option<u16>,
+    pub error: Option<String>,
+    pub last_attempt_at: Option<DateTime>,
+    /// When a pending delivery is retried, so retries survive a restart.
+    #[serde(default)]
+    pub next_attempt_at: Option<DateTime>,
+}
+
+impl WebhookDelivery {
+    pub fn new(id: ObjectId, webhook_id: ObjectId, event: WebhookEvent, payload: String) -> Self {
+        Self {
+            id: Some(id),
+            webhook_id,
+            event,
+            payload,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            response_status: None,
+            error: None,
+            last_attempt_at: None,
+            next_attempt_at: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicWebhookDelivery {
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    pub id: ObjectId,
+    #[serde(with = "bson_datetime_as_rfc3339_string")]
+    pub created_at: DateTime,
+    pub event: WebhookEvent,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub response_status: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl From<WebhookDelivery> for PublicWebhookDelivery {
+    fn from(delivery: WebhookDelivery) -> Self {
+        let id = delivery.id.unwrap();
+
+        Self {
+            id,
+            created_at: id.timestamp(),
+            event: delivery.event,
+            status: delivery.status,
+            attempts: delivery.attempts,
+            response_status: delivery.response_status,
+            error: delivery.error,
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use wither::bson::oid::ObjectId;
+
+use crate::{
+    errors::Error,
+    settings::{BucketSettings, RateLimitSettings},
+    utils::participant::Participant,
+};
+
+/// Past this many tracked keys, entries that no longer limit anyone are dropped.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Question,
+    Reaction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Participant(String),
+    Ip(IpAddr),
+}
+
+impl Key {
+    /// Every key the participant is known by, a request has to pass the limit on each of them
+    /// so a changed participant id alone does not get around it.
+    fn all(participant: &Participant) -> [Option<Self>; 2] {
+        [
+            participant.id.clone().map(Key::Participant),
+            participant.ip.map(Key::Ip),
+        ]
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(settings: BucketSettings, now: Instant) -> Self {
+        Self {
+            tokens: settings.burst.into(),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, settings: BucketSettings, now: Instant) {
+        let per_sec = f64::from(settings.per_minute) / 60.0;
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * per_sec).min(settings.burst.into());
+        self.updated = now;
+    }
+
+    /// Time until a token is available, zero if there is one already.
+    fn wait(&self, settings: BucketSettings) -> Duration {
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+
+        let per_sec = f64::from(settings.per_minute) / 60.0;
+        Duration::from_secs_f64((1.0 - self.tokens) / per_sec)
+    }
+
+    fn is_full(&self, settings: BucketSettings) -> bool {
+        self.tokens >= f64::from(settings.burst)
+    }
+}
+
+/// Token buckets per participant and per IP address, plus per-room slow mode.
+///
+/// Shared by the HTTP routes and the websocket sessions so both paths draw from the same
+/// budget.
+#[derive(Debug)]
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    buckets: Mutex<HashMap<(Action, Key), TokenBucket>>,
+    // next instant each participant may post in a room
+    slow_mode: Mutex<HashMap<(ObjectId, Key), Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self {
+            settings,
+            buckets: Mutex::new(HashMap::new()),
+            slow_mode: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn bucket_settings(&self, action: Action, key: &Key) -> BucketSettings {
+        match (action, key) {
+            (Action::Question, Key::Participant(_)) => self.settings.questions_per_participant,
+            (Action::Question, Key::Ip(_)) => self.settings.questions_per_ip,
+            (Action::Reaction, Key::Participant(_)) => self.settings.reactions_per_participant,
+            (Action::Reaction, Key::Ip(_)) => self.settings.reactions_per_ip,
+        }
+    }
+
+    /// Take a token for `action` from both the participant and the IP bucket.
+    ///
+    /// Nothing is taken unless both buckets have a token, so being limited on one key does
+    /// not drain the other.
+    pub fn check(&self, action: Action, participant: &Participant) -> Result<(), Error> {
+        if !self.settings.enabled {
+            return Ok(());
+        }
+
+        let keys = Key::all(participant);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|(action, key), bucket| {
+                let settings = self.bucket_settings(*action, key);
+                bucket.refill(settings, now);
+                !bucket.is_full(settings)
+            });
+        }
+
+        let mut retry_after = Duration::ZERO;
+        for key in keys.iter().flatten() {
+            let settings = self.bucket_settings(action, key);
+            let bucket = buckets
+                .entry((action, key.clone()))
+                .or_insert_with(|| TokenBucket::full(settings, now));
+
+            bucket.refill(settings, now);
+            retry_after = retry_after.max(bucket.wait(settings));
+        }
+
+        if !retry_after.is_zero() {
+            let message = match action {
+                Action::Question => "Too many questions, slow down",
+                Action::Reaction => "Too many reactions, slow down",
+            };
+            return Err(Error::too_many_requests(message, retry_after));
+        }
+
+        for key in keys.into_iter().flatten() {
+            if let Some(bucket) = buckets.get_mut(&(action, key)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Allow one question every `slow_mode_secs` per participant and per IP address in a room.
+    ///
+    pub fn check_slow_mode(
+        &self,
+        room_id: &ObjectId,
+        slow_mode_secs: u32,
+        participant: &Participant,
+    ) -> Result<(), Error> {
+        if !self.settings.enabled || slow_mode_secs == 0 {
+            return Ok(());
+        }
+
+        let keys = Key::all(participant);
+        let now = Instant::now();
+        let mut slow_mode = self.slow_mode.lock().unwrap();
+
+        if slow_mode.len() > PRUNE_THRESHOLD {
+            slow_mode.retain(|_, next_allowed| *next_allowed > now);
+        }
+
+        let retry_after = keys
+            .iter()
+            .flatten()
+            .filter_map(|key| slow_mode.get(&(*room_id, key.clone())))
+            .map(|next_allowed| next_allowed.saturating_duration_since(now))
+            .max()
+            .unwrap_or_default();
+        if !retry_after.is_zero() {
+            return Err(Error::too_many_requests(
+                format!("Slow mode is on, one question every {slow_mode_secs} seconds"),
+                retry_after,
+            ));
+        }
+
+        let next_allowed = now + Duration::from_secs(slow_mode_secs.into());
+        for key in keys.into_iter().flatten() {
+            slow_mode.insert((*room_id, key), next_allowed);
+        }
+
+        Ok(())
+    }
+}
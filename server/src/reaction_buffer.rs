@@ -0,0 +1,101 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    sync::Notify,
+    time::{interval, MissedTickBehavior},
+};
+use wither::bson::oid::ObjectId;
+
+use crate::{errors::Error, server::RoomServerHandle, services::question, settings};
+
+type Counts = HashMap<ObjectId, BTreeMap<String, i16>>;
+
+/// Legacy upvotes added up in memory and written to MongoDB in batches.
+///
+/// Hot rooms then cost one update per question and flush instead of one per click. Cloning is
+/// cheap and every clone shares the same counts.
+#[derive(Clone, Default)]
+pub struct ReactionBuffer {
+    enabled: bool,
+    counts: Arc<Mutex<Counts>>,
+    stop: Arc<Notify>,
+}
+
+impl ReactionBuffer {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Self::default()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Count a reaction of `kind` and return what is pending for the question.
+    ///
+    pub fn add(&self, question_id: ObjectId, kind: &str) -> BTreeMap<String, i16> {
+        // unwrap: the lock is never held across a panic
+        let mut counts = self.counts.lock().unwrap();
+        let pending = counts.entry(question_id).or_default();
+        let count = pending.entry(kind.to_owned()).or_default();
+        *count = count.saturating_add(1);
+
+        pending.clone()
+    }
+
+    /// Flush every `reactions.flush_interval_ms` until [`stop`](Self::stop) is called, then
+    /// one last time.
+    pub async fn run(self, room_server: RoomServerHandle) {
+        let mut ticks = interval(settings::get().reactions.flush_interval());
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => self.flush(&room_server).await,
+                _ = self.stop.notified() => break,
+            }
+        }
+
+        self.flush(&room_server).await;
+        tracing::info!("flushed buffered reactions before shutdown");
+    }
+
+    pub fn stop(&self) {
+        self.stop.notify_one();
+    }
+
+    /// Write the pending reactions and broadcast them.
+    ///
+    /// Counts that fail to be written for another reason than their question being gone are
+    /// put back for the next flush.
+    pub async fn flush(&self, room_server: &RoomServerHandle) {
+        let pending = std::mem::take(&mut *self.counts.lock().unwrap());
+
+        for (question_id, counts) in pending {
+            match question::add_buffered_reactions(room_server, question_id, counts.clone()).await {
+                Ok(_) => {}
+                Err(Error::NotFound(_)) => {
+                    tracing::debug!(%question_id, "dropping reactions of a removed question");
+                }
+                Err(err) => {
+                    tracing::error!(error = %err, %question_id, "failed to flush reactions");
+                    self.restore(question_id, counts);
+                }
+            }
+        }
+    }
+
+    fn restore(&self, question_id: ObjectId, counts: BTreeMap<String, i16>) {
+        let mut pending = self.counts.lock().unwrap();
+        let pending = pending.entry(question_id).or_default();
+        for (kind, count) in counts {
+            let total = pending.entry(kind).or_default();
+            *total = total.saturating_add(count);
+        }
+    }
+}
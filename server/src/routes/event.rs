@@ -0,0 +1,123 @@
+use actix_web::{
+    get, post,
+    web::{self, Path},
+    HttpResponse,
+};
+use bson::{doc, DateTime};
+use mime::APPLICATION_JSON;
+use serde::Deserialize;
+use wither::mongodb::options::FindOptions;
+
+use crate::{
+    errors::Error,
+    models::event::{Event, PublicEvent},
+    services::projection::{self, Snapshot},
+    utils::{host::Host, models::ModelExt, to_object_id::to_object_id},
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+pub fn create_routes(config: &mut web::ServiceConfig) {
+    config
+        .service(query_events)
+        .service(query_snapshot)
+        .service(rebuild_room);
+}
+
+/// The event log of a room, newest first.
+///
+/// Pass the id of the last event of a page as `before` to get the next one.
+#[get("/room/{id}/events")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+async fn query_events(
+    path: Path<String>,
+    query: web::Query<EventsQuery>,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let room_id =
+        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let mut filter = doc! { "room_id": room_id };
+    if let Some(before) = &query.before {
+        let before = to_object_id(before)
+            .map_err(|_| Error::bad_request("`before` must be an event id".into()))?;
+        filter.insert("_id", doc! { "$lt": before });
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let options = FindOptions::builder()
+        .sort(doc! { "_id": -1 })
+        .limit(limit)
+        .build();
+    let (events, _count) = Event::find_and_count(filter, options).await?;
+
+    let events = events
+        .into_iter()
+        .map(Into::into)
+        .collect::<Vec<PublicEvent>>();
+
+    Ok(HttpResponse::Ok()
+        .content_type(APPLICATION_JSON)
+        .json(events))
+}
+
+/// The room and its questions as they were at `at`, replayed from the event log.
+///
+#[get("/room/{id}/snapshot")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+async fn query_snapshot(
+    path: Path<String>,
+    query: web::Query<SnapshotQuery>,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let room_id =
+        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let at = match &query.at {
+        Some(at) => DateTime::parse_rfc3339_str(at).map_err(|_| {
+            Error::bad_request("`at` must be an RFC 3339 date, e.g. 2024-05-01T14:05:00Z".into())
+        })?,
+        None => DateTime::now(),
+    };
+
+    let board = projection::replay(room_id, Some(at))
+        .await?
+        .ok_or_else(|| Error::NotFound("Room has no recorded history at that time".into()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(APPLICATION_JSON)
+        .json(Snapshot::new(board, at)))
+}
+
+/// Overwrite the room and its questions with the state replayed from the event log.
+///
+#[post("/room/{id}/rebuild")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+async fn rebuild_room(path: Path<String>, _host: Host) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let room_id =
+        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let rebuild = projection::rebuild(room_id).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(APPLICATION_JSON)
+        .json(rebuild))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EventsQuery {
+    before: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SnapshotQuery {
+    at: Option<String>,
+}
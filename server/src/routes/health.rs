@@ -0,0 +1,126 @@
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use actix_web::{get, web, HttpResponse};
+use serde::Serialize;
+use serde_json::json;
+use tokio::{join, time::timeout};
+
+use crate::{
+    database,
+    server::{RoomServerHandle, RoomServerStats},
+};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+
+pub fn create_routes(config: &mut web::ServiceConfig) {
+    STARTED_AT.get_or_init(Instant::now);
+
+    config
+        .service(liveness)
+        .service(readiness)
+        .service(status);
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    ok: bool,
+    latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn new(started: Instant, error: Option<String>) -> Self {
+        Self {
+            ok: error.is_none(),
+            latency_ms: started.elapsed().as_millis(),
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RoomServerCheck {
+    #[serde(flatten)]
+    check: Check,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    stats: Option<RoomServerStats>,
+}
+
+async fn check_database() -> Check {
+    let started = Instant::now();
+
+    let error = match timeout(CHECK_TIMEOUT, database::ping()).await {
+        Ok(Ok(())) => None,
+        Ok(Err(err)) => Some(err.to_string()),
+        Err(_) => Some("database ping timed out".into()),
+    };
+
+    Check::new(started, error)
+}
+
+async fn check_room_server(room_server: &RoomServerHandle) -> RoomServerCheck {
+    let started = Instant::now();
+
+    let (stats, error) = match timeout(CHECK_TIMEOUT, room_server.stats()).await {
+        Ok(Some(stats)) => (Some(stats), None),
+        Ok(None) => (None, Some("room server is not running".into())),
+        Err(_) => (None, Some("room server did not answer in time".into())),
+    };
+
+    RoomServerCheck {
+        check: Check::new(started, error),
+        stats,
+    }
+}
+
+/// The process is up and serving requests.
+///
+#[get("/healthz")]
+async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// MongoDB answers a ping and the room server answers a round-trip command.
+///
+#[get("/readyz")]
+async fn readiness(room_server: web::Data<RoomServerHandle>) -> HttpResponse {
+    let (database, room_server) = join!(check_database(), check_room_server(&room_server));
+
+    if database.ok && room_server.check.ok {
+        HttpResponse::Ok().json(json!({ "status": "ready" }))
+    } else {
+        HttpResponse::ServiceUnavailable().json(json!({
+            "status": "unavailable",
+            "database": database,
+            "room_server": room_server,
+        }))
+    }
+}
+
+/// Detailed status for operators, including room and session counts.
+///
+#[get("/status")]
+async fn status(room_server: web::Data<RoomServerHandle>) -> HttpResponse {
+    let (database, room_server) = join!(check_database(), check_room_server(&room_server));
+
+    let ready = database.ok && room_server.check.ok;
+    let body = json!({
+        "status": if ready { "ready" } else { "unavailable" },
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": STARTED_AT.get().map_or(0, |started| started.elapsed().as_secs()),
+        "database": database,
+        "room_server": room_server,
+    });
+
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
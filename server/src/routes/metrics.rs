@@ -0,0 +1,16 @@
+use actix_web::{get, web, HttpResponse};
+
+use crate::{errors::Error, metrics};
+
+pub fn create_routes(config: &mut web::ServiceConfig) {
+    config.service(get_metrics);
+}
+
+#[get("/metrics")]
+async fn get_metrics() -> Result<HttpResponse, Error> {
+    let body = metrics::encode().map_err(|err| Error::InternalServerError(err.to_string()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
+}
@@ -1,2 +1,7 @@
+pub mod health;
 pub mod room;
 pub mod question;
+pub mod metrics;
+pub mod webhook;
+pub mod event;
+pub mod poll;
@@ -0,0 +1,135 @@
+use actix_web::{
+    get, post,
+    web::{self, Path},
+    HttpResponse,
+};
+use mime::APPLICATION_JSON;
+use serde::Deserialize;
+
+use crate::{
+    errors::Error,
+    models::{
+        event::{Actor, Source},
+        room::Room,
+    },
+    server::RoomServerHandle,
+    services::poll,
+    utils::{host::Host, models::ModelExt, participant::Participant, to_object_id::to_object_id},
+};
+
+pub fn create_routes(config: &mut web::ServiceConfig) {
+    config
+        .service(create_poll)
+        .service(query_polls)
+        .service(open_poll)
+        .service(close_poll)
+        .service(vote_poll);
+}
+
+#[post("/room/{id}/polls")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+async fn create_poll(
+    path: Path<String>,
+    json: web::Json<CreatePoll>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let body = json.into_inner();
+    let actor = Actor::new(&participant, Source::Http);
+
+    let public_poll = poll::create_poll(
+        &room_server,
+        &actor,
+        &path,
+        body.question,
+        body.options,
+        body.multiple,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(APPLICATION_JSON)
+        .json(public_poll))
+}
+
+#[get("/room/{id}/polls")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+async fn query_polls(path: Path<String>) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let room_id =
+        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    if Room::find_by_id(&room_id).await?.is_none() {
+        return Err(Error::NotFound("Room not found".into()));
+    }
+
+    let polls = poll::room_polls(room_id).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(APPLICATION_JSON)
+        .json(polls))
+}
+
+#[post("/poll/{id}/open")]
+#[tracing::instrument(skip_all, fields(poll_id = %path.as_str()))]
+async fn open_poll(
+    path: Path<String>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let actor = Actor::new(&participant, Source::Http);
+    let public_poll = poll::set_open(&room_server, &actor, &path, true).await?;
+
+    Ok(HttpResponse::Ok().json(public_poll))
+}
+
+#[post("/poll/{id}/close")]
+#[tracing::instrument(skip_all, fields(poll_id = %path.as_str()))]
+async fn close_poll(
+    path: Path<String>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let actor = Actor::new(&participant, Source::Http);
+    let public_poll = poll::set_open(&room_server, &actor, &path, false).await?;
+
+    Ok(HttpResponse::Ok().json(public_poll))
+}
+
+#[post("/poll/{id}/vote")]
+#[tracing::instrument(skip_all, fields(poll_id = %path.as_str()))]
+async fn vote_poll(
+    path: Path<String>,
+    json: web::Json<Vote>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+) -> Result<HttpResponse, Error> {
+    let actor = Actor::new(&participant, Source::Http);
+    let public_poll = poll::vote(
+        &room_server,
+        &participant,
+        &actor,
+        &path,
+        json.into_inner().choices,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(public_poll))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CreatePoll {
+    question: String,
+    options: Vec<String>,
+    #[serde(default)]
+    multiple: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Vote {
+    /// Positions of the chosen options.
+    choices: Vec<u32>,
+}
@@ -1,28 +1,72 @@
 use actix_web::{
-    delete, get, patch, post,
+    delete, get, patch, post, put,
     web::{self, Path},
     HttpResponse,
 };
-use bson::{bson, doc};
+use bson::doc;
 use mime::APPLICATION_JSON;
 use serde::{Deserialize, Serialize};
+use tracing::{field, Span};
 
 use crate::{
     errors::Error,
-    models::question::{PublicQuestion, Question},
+    models::{
+        event::{Actor, Source},
+        question::{PublicQuestion, Question},
+    },
+    rate_limit::RateLimiter,
+    reaction_buffer::ReactionBuffer,
     server::RoomServerHandle,
-    utils::{message_data::MessageData, models::ModelExt, to_object_id::to_object_id},
+    services::{
+        answer,
+        question::{self as services, NewQuestion},
+        search::{self, SearchParams},
+    },
+    utils::{host::Host, models::ModelExt, participant::Participant, to_object_id::to_object_id},
 };
 
 pub fn create_routes(config: &mut web::ServiceConfig) {
     config
         .service(create_question)
+        .service(search_questions)
         .service(get_question_by_id)
+        .service(get_question_author)
         .service(answer_question)
-        .service(react_question);
+        .service(delete_answer_question)
+        .service(set_written_answer)
+        .service(delete_written_answer)
+        .service(create_reply)
+        .service(update_reply)
+        .service(delete_reply)
+        .service(react_question)
+        .service(toggle_reaction)
+        .service(toggle_downvote)
+        .service(merge_question);
+}
+
+#[get("/questions/search")]
+#[tracing::instrument(skip_all, fields(room_id = field::Empty))]
+pub async fn search_questions(
+    query: web::Query<SearchParams>,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let room_id = match &query.room_id {
+        Some(id) => {
+            Span::current().record("room_id", id.as_str());
+            let room_id = to_object_id(id)
+                .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+            Some(room_id)
+        }
+        None => None,
+    };
+
+    let hits = search::search_questions(room_id, &query).await?;
+
+    Ok(HttpResponse::Ok().content_type(APPLICATION_JSON).json(hits))
 }
 
 #[get("/question/{id}")]
+#[tracing::instrument(skip_all, fields(question_id = %path.as_str(), room_id = field::Empty))]
 pub async fn get_question_by_id(path: Path<String>) -> Result<HttpResponse, Error> {
     let id = path.into_inner();
     let question_id =
@@ -35,155 +79,250 @@ pub async fn get_question_by_id(path: Path<String>) -> Result<HttpResponse, Erro
     }
 }
 
+/// Who asked a question, even when the room keeps names from the audience, host-only.
+///
+#[get("/question/{id}/author")]
+#[tracing::instrument(skip_all, fields(question_id = %path.as_str(), room_id = field::Empty))]
+async fn get_question_author(path: Path<String>, _host: Host) -> Result<HttpResponse, Error> {
+    let author = services::author(&path).await?;
+
+    Ok(HttpResponse::Ok().json(author))
+}
+
 #[patch("/question/{id}/answer")]
+#[tracing::instrument(skip_all, fields(question_id = %path.as_str(), room_id = field::Empty))]
 async fn answer_question(
     path: Path<String>,
     room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
 ) -> Result<HttpResponse, Error> {
-    let id = path.into_inner();
-    let question_id =
-        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+    let actor = Actor::new(&participant, Source::Http);
+    let public_question = services::set_answered(&room_server, &actor, &path, true).await?;
 
-    let updated_question = Question::find_one_and_update(
-        doc! { "_id": question_id },
-        doc! { "$set": bson!({
-            "answered": true
-        }) },
-    )
-    .await?;
-
-    match updated_question {
-        Some(question) => {
-            let public_question = PublicQuestion::from(question.clone());
-            let msg_data = MessageData::update(&public_question);
-            room_server
-                .send_message(question.room_id.to_string(), msg_data)
-                .await;
-
-            Ok(HttpResponse::Ok().json(PublicQuestion::from(question)))
-        }
-        None => Err(Error::NotFound("Question not found".into())),
-    }
+    Ok(HttpResponse::Ok().json(public_question))
 }
 
 #[delete("/question/{id}/answer")]
+#[tracing::instrument(skip_all, fields(question_id = %path.as_str(), room_id = field::Empty))]
 async fn delete_answer_question(
     path: Path<String>,
     room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
 ) -> Result<HttpResponse, Error> {
-    let id = path.into_inner();
-    let question_id =
-        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+    let actor = Actor::new(&participant, Source::Http);
+    let public_question = services::set_answered(&room_server, &actor, &path, false).await?;
+
+    Ok(HttpResponse::Ok().json(public_question))
+}
 
-    let updated_question = Question::find_one_and_update(
-        doc! { "_id": question_id },
-        doc! { "$set": bson!({
-            "answered": false
-        }) },
+/// Write the host's answer to a question, in Markdown, or rewrite it.
+///
+#[put("/question/{id}/written-answer")]
+#[tracing::instrument(skip_all, fields(question_id = %path.as_str(), room_id = field::Empty))]
+async fn set_written_answer(
+    path: Path<String>,
+    json: web::Json<WriteBody>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let actor = Actor::new(&participant, Source::Http);
+    let public_question =
+        answer::set_answer(&room_server, &actor, &path, json.into_inner().body).await?;
+
+    Ok(HttpResponse::Ok().json(public_question))
+}
+
+#[delete("/question/{id}/written-answer")]
+#[tracing::instrument(skip_all, fields(question_id = %path.as_str(), room_id = field::Empty))]
+async fn delete_written_answer(
+    path: Path<String>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let actor = Actor::new(&participant, Source::Http);
+    let public_question = answer::delete_answer(&room_server, &actor, &path).await?;
+
+    Ok(HttpResponse::Ok().json(public_question))
+}
+
+#[post("/question/{id}/replies")]
+#[tracing::instrument(skip_all, fields(question_id = %path.as_str(), room_id = field::Empty))]
+async fn create_reply(
+    path: Path<String>,
+    json: web::Json<WriteBody>,
+    room_server: web::Data<RoomServerHandle>,
+    rate_limiter: web::Data<RateLimiter>,
+    participant: Participant,
+) -> Result<HttpResponse, Error> {
+    let actor = Actor::new(&participant, Source::Http);
+    let public_question = answer::create_reply(
+        &room_server,
+        &rate_limiter,
+        &participant,
+        &actor,
+        &path,
+        json.into_inner().body,
     )
     .await?;
 
-    match updated_question {
-        Some(question) => {
-            let public_question = PublicQuestion::from(question.clone());
-            let msg_data = MessageData::update(&public_question);
-            room_server
-                .send_message(question.room_id.to_string(), msg_data)
-                .await;
+    Ok(HttpResponse::Ok().json(public_question))
+}
 
-            Ok(HttpResponse::Ok().json(PublicQuestion::from(question)))
-        }
-        None => Err(Error::NotFound("Question not found".into())),
-    }
+#[patch("/question/{id}/replies/{reply_id}")]
+#[tracing::instrument(skip_all, fields(question_id = %path.0, room_id = field::Empty))]
+async fn update_reply(
+    path: Path<(String, String)>,
+    json: web::Json<WriteBody>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+) -> Result<HttpResponse, Error> {
+    let (question_id, reply_id) = path.into_inner();
+    let actor = Actor::new(&participant, Source::Http);
+    let public_question = answer::update_reply(
+        &room_server,
+        &participant,
+        &actor,
+        &question_id,
+        &reply_id,
+        json.into_inner().body,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(public_question))
+}
+
+#[delete("/question/{id}/replies/{reply_id}")]
+#[tracing::instrument(skip_all, fields(question_id = %path.0, room_id = field::Empty))]
+async fn delete_reply(
+    path: Path<(String, String)>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+) -> Result<HttpResponse, Error> {
+    let (question_id, reply_id) = path.into_inner();
+    let actor = Actor::new(&participant, Source::Http);
+    let public_question =
+        answer::delete_reply(&room_server, &participant, &actor, &question_id, &reply_id).await?;
+
+    Ok(HttpResponse::Ok().json(public_question))
 }
 
 #[patch("/question/{id}/react")]
+#[tracing::instrument(skip_all, fields(question_id = %path.as_str(), room_id = field::Empty))]
 async fn react_question(
     path: Path<String>,
     room_server: web::Data<RoomServerHandle>,
+    rate_limiter: web::Data<RateLimiter>,
+    reaction_buffer: web::Data<ReactionBuffer>,
+    participant: Participant,
 ) -> Result<HttpResponse, Error> {
-    let id = path.into_inner();
-    let question_id =
-        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
-
-    let updated_question = Question::find_one_and_update(
-        doc! { "_id": question_id },
-        doc! { "$inc": bson!({
-            "reaction_count": 1
-        }) },
+    let public_question = services::react_question(
+        &room_server,
+        &rate_limiter,
+        &reaction_buffer,
+        &participant,
+        Source::Http,
+        &path,
     )
     .await?;
 
-    match updated_question {
-        Some(question) => {
-            let public_question = PublicQuestion::from(question.clone());
-            let msg_data = MessageData::update(&public_question);
-            room_server
-                .send_message(question.room_id.to_string(), msg_data)
-                .await;
+    Ok(HttpResponse::Ok().json(public_question))
+}
 
-            Ok(HttpResponse::Ok().json(PublicQuestion::from(question)))
-        }
-        None => Err(Error::NotFound("Question not found".into())),
-    }
+/// Add one of the room's reactions to a question, or take it back when the participant already
+/// has it.
+#[post("/question/{id}/reactions/{kind}")]
+#[tracing::instrument(skip_all, fields(question_id = %path.0, room_id = field::Empty))]
+async fn toggle_reaction(
+    path: Path<(String, String)>,
+    room_server: web::Data<RoomServerHandle>,
+    rate_limiter: web::Data<RateLimiter>,
+    participant: Participant,
+) -> Result<HttpResponse, Error> {
+    let (question_id, kind) = path.into_inner();
+    let toggled = services::toggle_reaction(
+        &room_server,
+        &rate_limiter,
+        &participant,
+        Source::Http,
+        &question_id,
+        &kind,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(toggled))
 }
 
-#[delete("/question/{id}/react")]
-async fn remove_react_question(
+/// Downvote a question in a room that allows it, or take the downvote back.
+///
+#[post("/question/{id}/downvote")]
+#[tracing::instrument(skip_all, fields(question_id = %path.as_str(), room_id = field::Empty))]
+async fn toggle_downvote(
     path: Path<String>,
     room_server: web::Data<RoomServerHandle>,
+    rate_limiter: web::Data<RateLimiter>,
+    participant: Participant,
 ) -> Result<HttpResponse, Error> {
-    let id = path.into_inner();
-    let question_id =
-        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
-
-    let updated_question = Question::find_one_and_update(
-        doc! { "_id": question_id },
-        doc! { "$dec": bson!({
-            "reaction_count": 1
-        }) },
+    let toggled = services::toggle_downvote(
+        &room_server,
+        &rate_limiter,
+        &participant,
+        Source::Http,
+        &path,
     )
     .await?;
 
-    match updated_question {
-        Some(question) => {
-            let public_question = PublicQuestion::from(question.clone());
-            let msg_data = MessageData::update(&public_question);
-            room_server
-                .send_message(question.room_id.to_string(), msg_data)
-                .await;
-
-            Ok(HttpResponse::Ok().json(PublicQuestion::from(question)))
-        }
-        None => Err(Error::NotFound("Question not found".into())),
-    }
+    Ok(HttpResponse::Ok().json(toggled))
 }
 
 #[post("/question")]
+#[tracing::instrument(skip_all, fields(room_id = %json.room_id, question_id = field::Empty))]
 async fn create_question(
     json: web::Json<CreateQuestion>,
     room_server: web::Data<RoomServerHandle>,
+    rate_limiter: web::Data<RateLimiter>,
+    participant: Participant,
 ) -> Result<HttpResponse, Error> {
-    let body = json.clone();
-
-    let room_id = to_object_id(body.room_id.clone())
-        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+    let body = json.into_inner();
 
-    let question = Question::new(room_id, body.value);
-    let question = Question::create(question).await;
-
-    let public_question =
-        question.map_err(|_| Error::InternalServerError("Failed to map question".into()))?;
-    let public_question = PublicQuestion::from(public_question);
-
-    let msg_data = MessageData::create(&public_question);
-    room_server.send_message(body.room_id, msg_data).await;
+    let public_question = services::create_question(
+        &room_server,
+        &rate_limiter,
+        &participant,
+        Source::Http,
+        &body.room_id,
+        NewQuestion {
+            value: body.value,
+            display_name: body.display_name,
+            force: body.force,
+        },
+    )
+    .await?;
 
     Ok(HttpResponse::Ok()
         .content_type(APPLICATION_JSON)
         .json(public_question))
 }
 
+#[post("/question/{id}/merge")]
+#[tracing::instrument(skip_all, fields(question_id = %path.as_str(), room_id = field::Empty))]
+async fn merge_question(
+    path: Path<String>,
+    json: web::Json<MergeQuestion>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let actor = Actor::new(&participant, Source::Http);
+    let public_question =
+        services::merge_questions(&room_server, &actor, &path, &json.into).await?;
+
+    Ok(HttpResponse::Ok().json(public_question))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdateQuestion {
     answered: Option<bool>,
@@ -194,4 +333,18 @@ pub struct UpdateQuestion {
 pub struct CreateQuestion {
     room_id: String,
     value: String,
+    display_name: Option<String>,
+    /// Post even when similar questions exist.
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeQuestion {
+    into: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WriteBody {
+    body: String,
 }
@@ -1,34 +1,66 @@
 use actix_web::{
-    get, post,
+    delete, get,
+    http::header::ContentDisposition,
+    post, put,
     web::{self, Path},
-    Error as ActixWebError, HttpRequest, HttpResponse,
+    Error as ActixWebError, HttpMessage, HttpRequest, HttpResponse,
 };
 use bson::doc;
 use mime::APPLICATION_JSON;
 use serde::Deserialize;
 use tokio::task::spawn_local;
+use tracing::{field, Instrument};
+use tracing_actix_web::RequestId;
 use wither::mongodb::options::FindOptions;
 
 use crate::{
     errors::Error,
     handler::room_subscribe_handle,
     models::{
+        event::{Actor, EventKind, Source},
         question::{PublicQuestion, Question},
-        room::{PublicRoom, Room},
+        room::{NameMode, PublicRoom, Room, SortMode},
+        webhook::WebhookEvent,
     },
+    rate_limit::RateLimiter,
+    reaction_buffer::ReactionBuffer,
     server::RoomServerHandle,
-    utils::{models::ModelExt, to_object_id::to_object_id},
+    services::{
+        announcement,
+        audit::{self, Change},
+        export::{self, ExportFormat},
+        import::{self, RoomImport},
+        projection, question as services,
+        search::{self, SearchParams},
+        spotlight,
+    },
+    settings,
+    utils::{host::Host, models::ModelExt, participant::Participant, to_object_id::to_object_id},
+    webhooks,
 };
 
 pub fn create_routes(config: &mut web::ServiceConfig) {
     config
         .service(create_room)
+        .service(import_room)
+        .service(close_room)
+        .service(set_spotlight)
+        .service(clear_spotlight)
+        .service(next_spotlight)
+        .service(announce)
         .service(get_room_by_id)
         .service(query_questions)
-        .service(room_subscribe);
+        .service(query_asked_questions)
+        .service(query_voted_questions)
+        .service(query_similar_questions)
+        .service(search_room_questions)
+        .service(export_room)
+        .service(room_subscribe)
+        .service(rooms_subscribe);
 }
 
 #[get("/room/{id}")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
 pub async fn get_room_by_id(path: Path<String>) -> Result<HttpResponse, Error> {
     let id = path.into_inner();
     let room_id =
@@ -42,21 +74,26 @@ pub async fn get_room_by_id(path: Path<String>) -> Result<HttpResponse, Error> {
 }
 
 #[get("/room/{id}/questions")]
-pub async fn query_questions(path: Path<String>) -> Result<HttpResponse, Error> {
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+pub async fn query_questions(
+    path: Path<String>,
+    query: web::Query<QuestionsQuery>,
+) -> Result<HttpResponse, Error> {
     let id = path.into_inner();
     let room_id =
         to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
 
-    if Room::find_by_id(&room_id).await?.is_none() {
-        return Err(Error::NotFound("Room not found".into()));
-    }
+    let room = Room::find_by_id(&room_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Room not found".into()))?;
 
-    let options = FindOptions::builder()
-        .sort(doc! { "created_at": -1_i32  })
-        .build();
+    let options = FindOptions::builder().sort(room.question_sort()).build();
 
-    let (questions, _count) =
-        Question::find_and_count(doc! { "room_id": room_id }, options).await?;
+    let mut filter = doc! { "room_id": room_id };
+    if !query.include_hidden {
+        filter.insert("hidden", doc! { "$ne": true });
+    }
+    let (questions, _count) = Question::find_and_count(filter, options).await?;
 
     let questions = questions
         .into_iter()
@@ -68,39 +105,403 @@ pub async fn query_questions(path: Path<String>) -> Result<HttpResponse, Error>
         .json(questions))
 }
 
+/// The questions the current participant asked in the room.
+///
+#[get("/room/{id}/questions/mine")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+pub async fn query_asked_questions(
+    path: Path<String>,
+    participant: Participant,
+) -> Result<HttpResponse, Error> {
+    let questions = services::asked_by(&participant, &path).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(APPLICATION_JSON)
+        .json(questions))
+}
+
+/// The questions of the room the current participant reacted to or downvoted.
+///
+#[get("/room/{id}/questions/reacted")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+pub async fn query_voted_questions(
+    path: Path<String>,
+    participant: Participant,
+) -> Result<HttpResponse, Error> {
+    let votes = services::voted_by(&participant, &path).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(APPLICATION_JSON)
+        .json(votes))
+}
+
+#[get("/room/{id}/questions/similar")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+pub async fn query_similar_questions(
+    path: Path<String>,
+    query: web::Query<SimilarQuery>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let room_id =
+        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let suggestions = services::find_similar(&room_id, &query.value).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(APPLICATION_JSON)
+        .json(suggestions))
+}
+
+#[get("/room/{id}/questions/search")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+pub async fn search_room_questions(
+    path: Path<String>,
+    query: web::Query<SearchParams>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let room_id =
+        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let hits = search::search_questions(Some(room_id), &query).await?;
+
+    Ok(HttpResponse::Ok().content_type(APPLICATION_JSON).json(hits))
+}
+
+#[get("/room/{id}/export")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+pub async fn export_room(
+    path: Path<String>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let room_id =
+        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let format = query.format;
+    let stream = export::export_room(room_id, format).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition::attachment(format!(
+            "room-{room_id}.{}",
+            format.extension()
+        )))
+        .streaming(stream))
+}
+
 #[post("/room")]
-async fn create_room(json: web::Json<CreateRoom>) -> Result<HttpResponse, Error> {
-    let room = Room::new(json.name.clone());
-    let room = Room::create(room).await?;
+#[tracing::instrument(skip_all, fields(room_id = field::Empty))]
+async fn create_room(
+    json: web::Json<CreateRoom>,
+    participant: Participant,
+) -> Result<HttpResponse, Error> {
+    let body = json.into_inner();
+    let slow_mode_secs = body
+        .slow_mode_secs
+        .unwrap_or(settings::get().rate_limit.default_slow_mode_secs);
+
+    let mut room = Room::new(body.name, slow_mode_secs);
+    room.sort_mode = body.sort_mode;
+    room.downvotes = body.downvotes;
+    room.hide_below = body.hide_below;
+    room.names = body.names;
+    if let Some(kinds) = body.reaction_kinds {
+        settings::validate_reaction_kinds(&kinds)
+            .map_err(|err| Error::bad_request(err.to_string()))?;
+        room.reaction_kinds = kinds;
+    }
+    let actor = Actor::new(&participant, Source::Http);
+    let room = audit::atomically(|| async {
+        let room = Room::create(room.clone()).await?;
+        audit::record(
+            &actor,
+            vec![Change {
+                room_id: room.id.unwrap(),
+                question_id: None,
+                kind: EventKind::RoomCreated,
+                before: None,
+                after: Some(&room),
+            }],
+        )
+        .await?;
+        Ok(room)
+    })
+    .await?;
+
     let public_room = PublicRoom::from(room);
+    tracing::Span::current().record("room_id", field::display(public_room.id));
 
     Ok(HttpResponse::Ok()
         .content_type(APPLICATION_JSON)
         .json(public_room))
 }
 
+#[post("/room/{id}/close")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+async fn close_room(
+    path: Path<String>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let room_id =
+        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let actor = Actor::new(&participant, Source::Http);
+    let (previous_room, room, events) = audit::atomically(|| async {
+        let previous_room = Room::find_one_and_update_before(
+            doc! { "_id": room_id },
+            doc! { "$set": { "closed": true } },
+        )
+        .await?
+        .ok_or_else(|| Error::NotFound("Room not found".into()))?;
+
+        let room = Room {
+            closed: true,
+            ..previous_room.clone()
+        };
+
+        // closing twice changes nothing, so it is neither recorded, broadcast nor sent to
+        // webhooks
+        if previous_room.closed {
+            return Ok((previous_room, room, Vec::new()));
+        }
+
+        let events = audit::record(
+            &actor,
+            vec![Change {
+                room_id,
+                question_id: None,
+                kind: EventKind::RoomClosed,
+                before: Some(&previous_room),
+                after: Some(&room),
+            }],
+        )
+        .await?;
+        Ok((previous_room, room, events))
+    })
+    .await?;
+    projection::broadcast(&room_server, &events).await;
+
+    let public_room = PublicRoom::from(room);
+    if !previous_room.closed {
+        webhooks::emit(room_id, WebhookEvent::RoomClosed, &public_room);
+    }
+
+    Ok(HttpResponse::Ok().json(public_room))
+}
+
+/// Put a question of the room in the spotlight, as the one being answered right now.
+///
+#[put("/room/{id}/spotlight")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+async fn set_spotlight(
+    path: Path<String>,
+    json: web::Json<SetSpotlight>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let actor = Actor::new(&participant, Source::Http);
+    let public_room = spotlight::set(&room_server, &actor, &path, Some(&json.question_id)).await?;
+
+    Ok(HttpResponse::Ok().json(public_room))
+}
+
+/// Send a message to everyone in the room, host-only.
+///
+#[post("/room/{id}/announcements")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+async fn announce(
+    path: Path<String>,
+    json: web::Json<Announce>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let actor = Actor::new(&participant, Source::Http);
+    let announcement =
+        announcement::announce(&room_server, &actor, &path, json.into_inner().body).await?;
+
+    Ok(HttpResponse::Created().json(announcement))
+}
+
+#[delete("/room/{id}/spotlight")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+async fn clear_spotlight(
+    path: Path<String>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let actor = Actor::new(&participant, Source::Http);
+    let public_room = spotlight::set(&room_server, &actor, &path, None).await?;
+
+    Ok(HttpResponse::Ok().json(public_room))
+}
+
+/// Mark the question in the spotlight as answered and put the next one in it.
+///
+#[post("/room/{id}/spotlight/next")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+async fn next_spotlight(
+    path: Path<String>,
+    room_server: web::Data<RoomServerHandle>,
+    participant: Participant,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let actor = Actor::new(&participant, Source::Http);
+    let public_room = spotlight::next(&room_server, &actor, &path).await?;
+
+    Ok(HttpResponse::Ok().json(public_room))
+}
+
+/// Import a room and its questions, from a JSON document or from CSV with the room given in
+/// the query string (`?key=...&name=...`).
+#[post("/room/import")]
+#[tracing::instrument(skip_all, fields(room_id = field::Empty))]
+async fn import_room(
+    req: HttpRequest,
+    body: web::Bytes,
+    participant: Participant,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let (room_import, rows) = if req.content_type() == "text/csv" {
+        let room_import = web::Query::<RoomImport>::from_query(req.query_string())
+            .map_err(|err| Error::bad_request(format!("Invalid import: {err}")))?
+            .into_inner();
+        (room_import, import::parse_csv(&body)?)
+    } else {
+        import::parse_json(&body)?
+    };
+
+    let actor = Actor::new(&participant, Source::Http);
+    let report = import::import(&actor, room_import, rows).await?;
+    tracing::Span::current().record("room_id", field::display(report.room.id));
+
+    Ok(HttpResponse::Ok()
+        .content_type(APPLICATION_JSON)
+        .json(report))
+}
+
+/// What a websocket session shares with the rest of the app.
+type SessionData = (
+    web::Data<RoomServerHandle>,
+    web::Data<RateLimiter>,
+    web::Data<ReactionBuffer>,
+);
+
+/// Who opened a websocket session, hosts send their token like on the HTTP routes.
+type Caller = (Participant, Option<Host>);
+
 #[get("/room/subscribe/{room_id}")]
 async fn room_subscribe(
     req: HttpRequest,
     stream: web::Payload,
     path: Path<String>,
-    room_server: web::Data<RoomServerHandle>,
+    data: SessionData,
+    caller: Caller,
+    request_id: RequestId,
+) -> Result<HttpResponse, ActixWebError> {
+    subscribe(
+        req,
+        stream,
+        data,
+        caller,
+        request_id,
+        Some(path.into_inner()),
+    )
+}
+
+/// A session following the rooms the client sends `Subscribe` commands for, with every message
+/// tagged with its `room_id`.
+#[get("/rooms/subscribe")]
+async fn rooms_subscribe(
+    req: HttpRequest,
+    stream: web::Payload,
+    data: SessionData,
+    caller: Caller,
+    request_id: RequestId,
+) -> Result<HttpResponse, ActixWebError> {
+    subscribe(req, stream, data, caller, request_id, None)
+}
+
+fn subscribe(
+    req: HttpRequest,
+    stream: web::Payload,
+    (room_server, rate_limiter, reaction_buffer): SessionData,
+    (participant, host): Caller,
+    request_id: RequestId,
+    room_id: Option<String>,
 ) -> Result<HttpResponse, ActixWebError> {
-    let room_id = path.into_inner();
     let (res, session, msg_stream) = actix_ws::handle(&req, stream)?;
 
+    // the session outlives the upgrade request, so it gets its own root span linked back to it
+    let span = tracing::info_span!(
+        parent: None,
+        "ws_session",
+        %request_id,
+        room_id = room_id.as_deref(),
+        session_id = field::Empty,
+    );
+    span.follows_from(tracing::Span::current());
+
     // spawn websocket handler (and don't await it) so that the response is returned immediately
-    spawn_local(room_subscribe_handle(
-        (**room_server).clone(),
-        session,
-        msg_stream,
-        room_id,
-    ));
+    spawn_local(
+        room_subscribe_handle(
+            (**room_server).clone(),
+            rate_limiter.into_inner(),
+            (**reaction_buffer).clone(),
+            participant,
+            host,
+            (session, msg_stream),
+            room_id,
+        )
+        .instrument(span),
+    );
 
     Ok(res)
 }
 
+#[derive(Debug, Clone, Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct QuestionsQuery {
+    /// Also list the questions hidden for their low score.
+    #[serde(default)]
+    include_hidden: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SimilarQuery {
+    value: String,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct CreateRoom {
     name: String,
+    slow_mode_secs: Option<u32>,
+    #[serde(default)]
+    sort_mode: SortMode,
+    #[serde(default)]
+    downvotes: bool,
+    hide_below: Option<i32>,
+    reaction_kinds: Option<Vec<String>>,
+    #[serde(default)]
+    names: NameMode,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SetSpotlight {
+    question_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Announce {
+    body: String,
 }
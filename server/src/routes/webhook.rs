@@ -0,0 +1,144 @@
+use actix_web::{
+    delete, get, post,
+    web::{self, Path},
+    HttpResponse,
+};
+use bson::doc;
+use mime::APPLICATION_JSON;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use wither::mongodb::options::FindOptions;
+
+use crate::{
+    errors::Error,
+    models::{
+        room::Room,
+        webhook::{PublicWebhook, PublicWebhookDelivery, Webhook, WebhookDelivery, WebhookEvent},
+    },
+    utils::{host::Host, models::ModelExt, to_object_id::to_object_id},
+};
+
+const DELIVERIES_PAGE_SIZE: i64 = 50;
+
+pub fn create_routes(config: &mut web::ServiceConfig) {
+    config
+        .service(create_webhook)
+        .service(query_webhooks)
+        .service(delete_webhook)
+        .service(query_deliveries);
+}
+
+#[post("/room/{id}/webhooks")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+async fn create_webhook(
+    path: Path<String>,
+    json: web::Json<CreateWebhook>,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let room_id =
+        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    if Room::find_by_id(&room_id).await?.is_none() {
+        return Err(Error::NotFound("Room not found".into()));
+    }
+
+    let body = json.into_inner();
+    let secret = Uuid::new_v4().simple().to_string();
+    let webhook = Webhook::new(room_id, body.url, body.events, secret.clone());
+    let webhook = Webhook::create(webhook).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(APPLICATION_JSON)
+        .json(CreatedWebhook {
+            webhook: PublicWebhook::from(webhook),
+            secret,
+        }))
+}
+
+#[get("/room/{id}/webhooks")]
+#[tracing::instrument(skip_all, fields(room_id = %path.as_str()))]
+async fn query_webhooks(path: Path<String>, _host: Host) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let room_id =
+        to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let (webhooks, _count) = Webhook::find_and_count(doc! { "room_id": room_id }, None).await?;
+
+    let webhooks = webhooks
+        .into_iter()
+        .map(Into::into)
+        .collect::<Vec<PublicWebhook>>();
+
+    Ok(HttpResponse::Ok()
+        .content_type(APPLICATION_JSON)
+        .json(webhooks))
+}
+
+#[delete("/room/{id}/webhooks/{webhook_id}")]
+#[tracing::instrument(skip_all, fields(room_id = %path.0))]
+async fn delete_webhook(path: Path<(String, String)>, _host: Host) -> Result<HttpResponse, Error> {
+    let (room_id, webhook_id) = path.into_inner();
+    let room_id = to_object_id(room_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+    let webhook_id = to_object_id(webhook_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let deleted =
+        Webhook::find_one_and_delete(doc! { "_id": webhook_id, "room_id": room_id }).await?;
+
+    match deleted {
+        Some(webhook) => Ok(HttpResponse::Ok().json(PublicWebhook::from(webhook))),
+        None => Err(Error::NotFound("Webhook not found".into())),
+    }
+}
+
+/// The latest deliveries of a webhook, newest first.
+///
+#[get("/room/{id}/webhooks/{webhook_id}/deliveries")]
+#[tracing::instrument(skip_all, fields(room_id = %path.0))]
+async fn query_deliveries(
+    path: Path<(String, String)>,
+    _host: Host,
+) -> Result<HttpResponse, Error> {
+    let (room_id, webhook_id) = path.into_inner();
+    let room_id = to_object_id(room_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+    let webhook_id = to_object_id(webhook_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let webhook = Webhook::find_one(doc! { "_id": webhook_id, "room_id": room_id }).await?;
+    if webhook.is_none() {
+        return Err(Error::NotFound("Webhook not found".into()));
+    }
+
+    let options = FindOptions::builder()
+        .sort(doc! { "_id": -1 })
+        .limit(DELIVERIES_PAGE_SIZE)
+        .build();
+    let (deliveries, _count) =
+        WebhookDelivery::find_and_count(doc! { "webhook_id": webhook_id }, options).await?;
+
+    let deliveries = deliveries
+        .into_iter()
+        .map(Into::into)
+        .collect::<Vec<PublicWebhookDelivery>>();
+
+    Ok(HttpResponse::Ok()
+        .content_type(APPLICATION_JSON)
+        .json(deliveries))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CreateWebhook {
+    url: String,
+    events: Vec<WebhookEvent>,
+}
+
+#[derive(Debug, Serialize)]
+struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: PublicWebhook,
+    /// Only returned here, receivers need it to check the `x-ama-signature` header.
+    secret: String,
+}
@@ -1,15 +1,42 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
 use std::{
     collections::{HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
     io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{
+    runtime,
+    sync::{mpsc, oneshot},
+    task::spawn_blocking,
+    time::{interval, MissedTickBehavior},
 };
-use tokio::sync::{mpsc, oneshot};
+use tracing::{field, Instrument, Span};
 use uuid::Uuid;
 
+use crate::metrics;
+
 #[derive(Debug)]
 enum Command {
-    Connect {
+    Subscribe {
+        conn: Uuid,
         conn_tx: mpsc::UnboundedSender<String>,
-        res_tx: oneshot::Sender<Uuid>,
+        room_id: String,
+        participant_id: Option<String>,
+        /// Whether the session follows several rooms and needs to be told which one a message
+        /// comes from.
+        tagged: bool,
+        res_tx: oneshot::Sender<Joined>,
+    },
+
+    Unsubscribe {
+        conn: Uuid,
         room_id: String,
     },
 
@@ -20,158 +47,644 @@ enum Command {
     Message {
         msg: String,
         room_id: String,
+        involves: Vec<String>,
         res_tx: oneshot::Sender<()>,
     },
+
+    Coalesce {
+        msg: String,
+        room_id: String,
+        key: String,
+        involves: Vec<String>,
+    },
+
+    /// Answered with the number of rooms of the shard.
+    Stats {
+        res_tx: oneshot::Sender<usize>,
+    },
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Subscribe { .. } => "subscribe",
+            Command::Unsubscribe { .. } => "unsubscribe",
+            Command::Disconnect { .. } => "disconnect",
+            Command::Message { .. } => "message",
+            Command::Coalesce { .. } => "coalesce",
+            Command::Stats { .. } => "stats",
+        }
+    }
 }
 
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RoomServerStats {
+    pub rooms: usize,
+    pub sessions: usize,
+}
+
+/// Where a room stood when a session joined it.
+///
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Joined {
+    /// Sequence number of the last message sent to the room before the session joined, the
+    /// ones it receives carry higher numbers.
+    pub seq: u64,
+    /// Sessions following the room, the new one included.
+    pub presence: usize,
+}
+
+/// Commands travel with the span they were issued from, so the room server's logs can be
+/// correlated with the request or session that caused them.
+type Envelope = (Command, Span);
+
 #[derive(Clone)]
 pub struct RoomServerHandle {
-    cmd_tx: mpsc::UnboundedSender<Command>,
+    shards: Arc<[mpsc::UnboundedSender<Envelope>]>,
+    /// Open sessions, counted here since one following several rooms can be known to several
+    /// shards.
+    sessions: Arc<AtomicUsize>,
 }
 
 impl RoomServerHandle {
-    pub async fn connect(&self, conn_tx: mpsc::UnboundedSender<String>, room_id: String) -> Uuid {
+    fn shard(&self, room_id: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        room_id.hash(&mut hasher);
+        hasher.finish() as usize % self.shards.len()
+    }
+
+    /// Every command about a room goes to the shard its id hashes to.
+    ///
+    fn send(&self, room_id: &str, cmd: Command) {
+        // unwrap: room server should not have been dropped
+        self.shards[self.shard(room_id)]
+            .send((cmd, Span::current()))
+            .unwrap();
+    }
+
+    /// Open a session following a single room, its messages are sent as they are.
+    ///
+    pub async fn connect(
+        &self,
+        conn_tx: mpsc::UnboundedSender<String>,
+        room_id: String,
+        participant_id: Option<String>,
+    ) -> (Uuid, Joined) {
+        let conn = self.open();
+        let joined = self
+            .add_subscription(conn, conn_tx, room_id, participant_id, false)
+            .await;
+
+        (conn, joined)
+    }
+
+    /// Open a session that follows rooms with [`subscribe`](Self::subscribe), starting with
+    /// none.
+    pub fn open(&self) -> Uuid {
+        let conn = Uuid::new_v4();
+        Span::current().record("session_id", field::display(conn));
+        self.sessions.fetch_add(1, Ordering::Relaxed);
+        metrics::ACTIVE_SESSIONS.inc();
+
+        conn
+    }
+
+    /// Add a room to an open session, its messages then come with a `room_id` field.
+    ///
+    /// Returns once the room server is sending the room's messages to the session.
+    pub async fn subscribe(
+        &self,
+        conn: Uuid,
+        conn_tx: mpsc::UnboundedSender<String>,
+        room_id: String,
+        participant_id: Option<String>,
+    ) -> Joined {
+        self.add_subscription(conn, conn_tx, room_id, participant_id, true)
+            .await
+    }
+
+    async fn add_subscription(
+        &self,
+        conn: Uuid,
+        conn_tx: mpsc::UnboundedSender<String>,
+        room_id: String,
+        participant_id: Option<String>,
+        tagged: bool,
+    ) -> Joined {
         let (res_tx, res_rx) = oneshot::channel();
 
-        self.cmd_tx
-            .send(Command::Connect {
+        self.send(
+            &room_id.clone(),
+            Command::Subscribe {
+                conn,
                 conn_tx,
-                res_tx,
                 room_id,
-            })
-            .unwrap();
+                participant_id,
+                tagged,
+                res_tx,
+            },
+        );
 
         res_rx.await.unwrap()
     }
 
-    pub fn disconnect(&self, conn: Uuid) {
-        // unwrap: room server should not have been dropped
-        self.cmd_tx.send(Command::Disconnect { conn }).unwrap();
+    pub fn unsubscribe(&self, conn: Uuid, room_id: String) {
+        self.send(&room_id.clone(), Command::Unsubscribe { conn, room_id });
+    }
+
+    /// Close a session, leaving every room it still follows.
+    ///
+    pub fn disconnect<'a>(&self, conn: Uuid, rooms: impl IntoIterator<Item = &'a str>) {
+        let shards = rooms
+            .into_iter()
+            .map(|room_id| self.shard(room_id))
+            .collect::<HashSet<_>>();
+
+        for shard in shards {
+            // unwrap: room server should not have been dropped
+            self.shards[shard]
+                .send((Command::Disconnect { conn }, Span::current()))
+                .unwrap();
+        }
+
+        self.sessions.fetch_sub(1, Ordering::Relaxed);
+        metrics::ACTIVE_SESSIONS.dec();
     }
 
     pub async fn send_message(&self, room_id: String, msg: impl Into<String>) {
+        self.send_message_involving(room_id, msg, Vec::new()).await;
+    }
+
+    /// Send a message about a change that concerns the participants in `involves`, like the
+    /// author of a question, their sessions receive it with `involves_you` set.
+    pub async fn send_message_involving(
+        &self,
+        room_id: String,
+        msg: impl Into<String>,
+        involves: Vec<String>,
+    ) {
         let (res_tx, res_rx) = oneshot::channel();
 
-        self.cmd_tx
-            .send(Command::Message {
+        self.send(
+            &room_id.clone(),
+            Command::Message {
                 msg: msg.into(),
                 room_id,
+                involves,
                 res_tx,
-            })
-            .unwrap();
+            },
+        );
 
         res_rx.await.unwrap()
     }
+
+    /// Send a message that a later one with the same `key` replaces, if it comes within the
+    /// broadcast window.
+    ///
+    /// Returns right away, the message is sent at the end of the window or before the next
+    /// plain message to the room, whichever comes first.
+    pub fn send_coalesced(
+        &self,
+        room_id: String,
+        key: String,
+        msg: impl Into<String>,
+        involves: Vec<String>,
+    ) {
+        self.send(
+            &room_id.clone(),
+            Command::Coalesce {
+                msg: msg.into(),
+                room_id,
+                key,
+                involves,
+            },
+        );
+    }
+
+    /// Round-trip through every shard of the room server, returns `None` if it is no longer
+    /// running.
+    pub async fn stats(&self) -> Option<RoomServerStats> {
+        let mut rooms = 0;
+
+        for shard in self.shards.iter() {
+            let (res_tx, res_rx) = oneshot::channel();
+            shard
+                .send((Command::Stats { res_tx }, Span::current()))
+                .ok()?;
+
+            rooms += res_rx.await.ok()?;
+        }
+
+        Some(RoomServerStats {
+            rooms,
+            sessions: self.sessions.load(Ordering::Relaxed),
+        })
+    }
 }
 
+/// Rooms and sessions partitioned across shards, each running on its own thread.
+///
 #[derive(Debug)]
 pub struct RoomServer {
-    sessions: HashMap<Uuid, mpsc::UnboundedSender<String>>,
-    rooms: HashMap<String, HashSet<Uuid>>,
-    cmd_rx: mpsc::UnboundedReceiver<Command>,
+    shards: Vec<Shard>,
 }
 
 impl RoomServer {
-    pub fn new() -> (Self, RoomServerHandle) {
-        let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+    /// Coalesced messages are held for up to `window`, a zero window sends them at once.
+    pub fn new(shards: usize, window: Duration) -> (Self, RoomServerHandle) {
+        let (shards, senders): (Vec<_>, Vec<_>) = (0..shards.max(1))
+            .map(|index| {
+                let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+                (Shard::new(index, cmd_rx, window), cmd_tx)
+            })
+            .unzip();
 
         (
-            RoomServer {
-                sessions: HashMap::new(),
-                rooms: HashMap::new(),
-                cmd_rx,
+            RoomServer { shards },
+            RoomServerHandle {
+                shards: senders.into(),
+                sessions: Arc::default(),
             },
-            RoomServerHandle { cmd_tx },
         )
     }
 
+    /// Run the shards until every handle is dropped.
+    ///
+    pub async fn run(self) -> io::Result<()> {
+        let threads = self
+            .shards
+            .into_iter()
+            .map(|shard| {
+                thread::Builder::new()
+                    .name(format!("room-shard-{}", shard.index))
+                    .spawn(move || {
+                        runtime::Builder::new_current_thread()
+                            .enable_time()
+                            .build()?
+                            .block_on(shard.run())
+                    })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        for thread in threads {
+            spawn_blocking(move || thread.join())
+                .await
+                .map_err(io::Error::other)?
+                .map_err(|_| io::Error::other("room server shard panicked"))??;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    tx: mpsc::UnboundedSender<String>,
+    /// The rooms of this shard the session follows.
+    rooms: HashSet<String>,
+    participant_id: Option<String>,
+    tagged: bool,
+}
+
+/// The sessions following a room, and the sequence number of the last message sent to it.
+///
+#[derive(Debug, Default)]
+struct Subscribers {
+    sessions: HashSet<Uuid>,
+    seq: u64,
+}
+
+/// Messages of a room held back until the end of the broadcast window, in the order their key
+/// was first seen.
+#[derive(Debug, Default)]
+struct Pending {
+    keys: Vec<String>,
+    messages: HashMap<String, (String, Vec<String>)>,
+}
+
+/// The rooms whose id hashes to `index`, and the sessions subscribed to them.
+///
+/// A session following rooms on several shards is known to each of them.
+///
+#[derive(Debug)]
+struct Shard {
+    index: usize,
+    sessions: HashMap<Uuid, Session>,
+    rooms: HashMap<String, Subscribers>,
+    pending: HashMap<String, Pending>,
+    window: Duration,
+    cmd_rx: mpsc::UnboundedReceiver<Envelope>,
+}
+
+impl Shard {
+    fn new(index: usize, cmd_rx: mpsc::UnboundedReceiver<Envelope>, window: Duration) -> Self {
+        Shard {
+            index,
+            sessions: HashMap::new(),
+            rooms: HashMap::new(),
+            pending: HashMap::new(),
+            window,
+            cmd_rx,
+        }
+    }
+
     /// Send message to users in a room.
     ///
     async fn send_system_message(&self, room: &str, msg: impl Into<String>) {
-        if let Some(sessions) = self.rooms.get(room) {
+        if let Some(subscribers) = self.rooms.get(room) {
+            let started = Instant::now();
             let msg = msg.into();
+            let sessions = &subscribers.sessions;
+            let mut tagged = None;
 
             for conn_id in sessions {
-                if let Some(tx) = self.sessions.get(conn_id) {
+                if let Some(session) = self.sessions.get(conn_id) {
+                    let msg = if session.tagged {
+                        tagged.get_or_insert_with(|| tag(room, &msg)).clone()
+                    } else {
+                        msg.clone()
+                    };
                     // errors if client disconnected abruptly and hasn't been timed-out yet
-                    let _ = tx.send(msg.clone());
+                    let _ = session.tx.send(msg);
                 }
             }
+
+            metrics::BROADCAST_RECIPIENTS.observe(sessions.len() as f64);
+            metrics::BROADCAST_DURATION.observe(started.elapsed().as_secs_f64());
         }
     }
 
+    /// Send an event to users in a room, numbered after the previous one and telling each
+    /// session whether its participant is among those it `involves`.
+    async fn send_event(&mut self, room: &str, msg: String, involves: &[String]) {
+        let Some(subscribers) = self.rooms.get_mut(room) else {
+            return;
+        };
+        let started = Instant::now();
+        subscribers.seq += 1;
+        let seq = subscribers.seq;
+
+        // each variant of the message, involved or not and tagged or not, is built once
+        let mut variants: [Option<String>; 4] = Default::default();
+        for conn_id in &subscribers.sessions {
+            if let Some(session) = self.sessions.get(conn_id) {
+                let involved = session
+                    .participant_id
+                    .as_ref()
+                    .is_some_and(|id| involves.contains(id));
+                let variant = usize::from(involved) * 2 + usize::from(session.tagged);
+                let msg = variants[variant].get_or_insert_with(|| {
+                    let msg = stamp(seq, involved, &msg);
+                    if session.tagged {
+                        tag(room, &msg)
+                    } else {
+                        msg
+                    }
+                });
+                // errors if client disconnected abruptly and hasn't been timed-out yet
+                let _ = session.tx.send(msg.clone());
+            }
+        }
+
+        metrics::BROADCAST_RECIPIENTS.observe(subscribers.sessions.len() as f64);
+        metrics::BROADCAST_DURATION.observe(started.elapsed().as_secs_f64());
+    }
+
     /// Send message to all other users in current room.
     ///
-    async fn send_message(&self, room_id: String, msg: impl Into<String>) {
+    async fn send_message(&mut self, room_id: String, msg: String, involves: &[String]) {
         if self.rooms.contains_key(&room_id) {
-            self.send_system_message(&room_id, msg).await;
-            log::info!("message broadcasted to room {room_id}");
+            // held back messages are older, clients must not see them after this one
+            self.flush_room(&room_id).await;
+            self.send_event(&room_id, msg, involves).await;
+            tracing::info!("message broadcasted to room");
+        } else {
+            tracing::info!("room not found to broadcast message");
+        }
+    }
+
+    /// Hold a message back, replacing the pending one with the same key.
+    ///
+    async fn coalesce(&mut self, room_id: String, key: String, msg: String, involves: Vec<String>) {
+        if !self.rooms.contains_key(&room_id) {
+            return;
+        }
+        if self.window.is_zero() {
+            self.send_event(&room_id, msg, &involves).await;
+            return;
+        }
+
+        let pending = self.pending.entry(room_id).or_default();
+        if pending
+            .messages
+            .insert(key.clone(), (msg, involves))
+            .is_some()
+        {
+            metrics::COALESCED_MESSAGES.inc();
         } else {
-            log::info!("room not found to broadcast message");
+            pending.keys.push(key);
+        }
+    }
+
+    async fn flush_room(&mut self, room_id: &str) {
+        if let Some(mut pending) = self.pending.remove(room_id) {
+            for key in pending.keys {
+                if let Some((msg, involves)) = pending.messages.remove(&key) {
+                    self.send_event(room_id, msg, &involves).await;
+                }
+            }
+        }
+    }
+
+    async fn flush_pending(&mut self) {
+        let rooms = self.pending.keys().cloned().collect::<Vec<_>>();
+        for room_id in rooms {
+            self.flush_room(&room_id).await;
         }
     }
 
-    /// Register new session and assign unique ID to this session
+    /// Add a room to a session, registering the session with this shard if it is new to it.
     ///
-    async fn connect(&mut self, tx: mpsc::UnboundedSender<String>, room_id: String) -> Uuid {
-        self.send_system_message(&room_id, "Someone joined").await;
+    async fn subscribe(
+        &mut self,
+        session_id: Uuid,
+        tx: mpsc::UnboundedSender<String>,
+        room_id: String,
+        participant_id: Option<String>,
+        tagged: bool,
+    ) -> Joined {
+        let session = self.sessions.entry(session_id).or_insert_with(|| Session {
+            tx,
+            rooms: HashSet::new(),
+            participant_id,
+            tagged,
+        });
+        if session.rooms.insert(room_id.clone()) {
+            self.send_system_message(&room_id, "Someone joined").await;
+        }
 
-        let id = Uuid::new_v4();
-        self.sessions.insert(id, tx);
+        let subscribers = self.rooms.entry(room_id).or_default();
+        if subscribers.sessions.is_empty() {
+            metrics::ACTIVE_ROOMS.inc();
+        }
+        subscribers.sessions.insert(session_id);
 
-        self.rooms.entry(room_id).or_default().insert(id);
+        Joined {
+            seq: subscribers.seq,
+            presence: subscribers.sessions.len(),
+        }
+    }
 
-        id
+    async fn unsubscribe(&mut self, session_id: Uuid, room_id: String) {
+        let Some(session) = self.sessions.get_mut(&session_id) else {
+            return;
+        };
+        if !session.rooms.remove(&room_id) {
+            return;
+        }
+        if session.rooms.is_empty() {
+            self.sessions.remove(&session_id);
+        }
+
+        self.leave(session_id, room_id).await;
     }
 
-    /// Unregister connection from room map and broadcast disconnection message.
+    /// Unregister connection from the rooms of this shard and broadcast disconnection message.
     ///
     async fn disconnect(&mut self, session_id: Uuid) {
-        println!("Someone disconnected");
+        let Some(session) = self.sessions.remove(&session_id) else {
+            return;
+        };
 
-        let mut room = String::new();
+        tracing::info!("session disconnected");
+        for room_id in session.rooms {
+            self.leave(session_id, room_id).await;
+        }
+    }
 
-        // remove sender
-        if self.sessions.remove(&session_id).is_some() {
-            // remove session from all rooms
-            for (room_id, sessions) in &mut self.rooms {
-                if sessions.remove(&session_id) {
-                    room.clone_from(room_id);
-                }
+    async fn leave(&mut self, session_id: Uuid, room: String) {
+        if let Some(subscribers) = self.rooms.get_mut(&room) {
+            subscribers.sessions.remove(&session_id);
+
+            // forget rooms nobody is subscribed to anymore
+            if subscribers.sessions.is_empty() {
+                self.rooms.remove(&room);
+                self.pending.remove(&room);
+                metrics::ACTIVE_ROOMS.dec();
             }
         }
 
-        if !room.is_empty() {
-            self.send_system_message(&room, "session {session_id} has disconnected.")
-                .await;
+        self.send_system_message(&room, format!("session {session_id} has disconnected."))
+            .await;
+    }
+
+    async fn handle(&mut self, cmd: Command) {
+        match cmd {
+            Command::Subscribe {
+                conn,
+                conn_tx,
+                room_id,
+                participant_id,
+                tagged,
+                res_tx,
+            } => {
+                Span::current().record("room_id", room_id.as_str());
+                Span::current().record("session_id", field::display(conn));
+                let joined = self
+                    .subscribe(conn, conn_tx, room_id, participant_id, tagged)
+                    .await;
+                let _ = res_tx.send(joined);
+            }
+            Command::Unsubscribe { conn, room_id } => {
+                Span::current().record("room_id", room_id.as_str());
+                Span::current().record("session_id", field::display(conn));
+                self.unsubscribe(conn, room_id).await;
+            }
+            Command::Disconnect { conn } => {
+                Span::current().record("session_id", field::display(conn));
+                self.disconnect(conn).await;
+            }
+            Command::Message {
+                room_id,
+                msg,
+                involves,
+                res_tx,
+            } => {
+                Span::current().record("room_id", room_id.as_str());
+                self.send_message(room_id, msg, &involves).await;
+                let _ = res_tx.send(());
+            }
+            Command::Coalesce {
+                msg,
+                room_id,
+                key,
+                involves,
+            } => {
+                Span::current().record("room_id", room_id.as_str());
+                self.coalesce(room_id, key, msg, involves).await;
+            }
+            Command::Stats { res_tx } => {
+                let _ = res_tx.send(self.rooms.len());
+            }
         }
     }
 
-    pub async fn run(mut self) -> io::Result<()> {
-        while let Some(cmd) = self.cmd_rx.recv().await {
-            match cmd {
-                Command::Connect {
-                    conn_tx,
-                    res_tx,
-                    room_id,
-                } => {
-                    let conn_id = self.connect(conn_tx, room_id).await;
-                    let _ = res_tx.send(conn_id);
-                }
-                Command::Disconnect { conn } => {
-                    self.disconnect(conn).await;
-                }
-                Command::Message {
-                    room_id,
-                    msg,
-                    res_tx,
-                } => {
-                    self.send_message(room_id, msg).await;
-                    let _ = res_tx.send(());
+    async fn run(mut self) -> io::Result<()> {
+        // only polled while messages are pending: after a quiet spell the missed tick fires at
+        // once, so the first message goes out right away and the ones after it are coalesced
+        let mut flush = interval(self.window.max(Duration::from_millis(1)));
+        flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                envelope = self.cmd_rx.recv() => {
+                    let Some((cmd, parent)) = envelope else {
+                        break;
+                    };
+                    let span = tracing::info_span!(
+                        parent: &parent,
+                        "room_server",
+                        command = cmd.name(),
+                        shard = self.index,
+                        room_id = field::Empty,
+                        session_id = field::Empty,
+                    );
+
+                    self.handle(cmd).instrument(span).await;
                 }
+                _ = flush.tick(), if !self.pending.is_empty() => self.flush_pending().await,
             }
         }
 
+        self.flush_pending().await;
+
         Ok(())
     }
 }
+
+/// Add the sequence number and whether the recipient is involved to a message, as its first
+/// fields so a JSON object is not parsed again for every broadcast.
+///
+/// Messages that are not JSON objects are left as they are.
+fn stamp(seq: u64, involves_you: bool, msg: &str) -> String {
+    match msg.strip_prefix('{') {
+        Some(fields) if !fields.trim_start().starts_with('}') => {
+            format!("{{\"seq\":{seq},\"involves_you\":{involves_you},{fields}")
+        }
+        _ => msg.to_owned(),
+    }
+}
+
+/// A message as sent to sessions following several rooms, with the room it comes from.
+///
+/// Messages that are not JSON objects, like the join and leave notices, are wrapped in a
+/// `Notice`.
+pub fn tag(room_id: &str, msg: &str) -> String {
+    match serde_json::from_str::<Map<String, Value>>(msg) {
+        Ok(mut fields) => {
+            fields.insert("room_id".into(), room_id.into());
+            Value::Object(fields).to_string()
+        }
+        Err(_) => json!({ "room_id": room_id, "kind": "Notice", "data": msg }).to_string(),
+    }
+}
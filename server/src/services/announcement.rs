@@ -0,0 +1,81 @@
+use bson::doc;
+use wither::bson::{oid::ObjectId, DateTime};
+
+use crate::{
+    errors::Error,
+    models::{
+        event::{Actor, EventKind},
+        room::{Announcement, PublicAnnouncement, Room, MAX_ANNOUNCEMENTS},
+    },
+    server::RoomServerHandle,
+    services::{
+        audit::{self, Change},
+        projection,
+    },
+    utils::{models::ModelExt, to_object_id::to_object_id},
+};
+
+const MAX_ANNOUNCEMENT_LEN: usize = 280;
+
+/// Send a message of the hosts to everyone in the room, and keep it with the latest ones for
+/// those who join later.
+pub async fn announce(
+    room_server: &RoomServerHandle,
+    actor: &Actor,
+    room_id: &str,
+    body: String,
+) -> Result<PublicAnnouncement, Error> {
+    let room_id = to_object_id(room_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let body = body.trim();
+    if body.is_empty() {
+        return Err(Error::bad_request("Announcement cannot be empty".into()));
+    }
+    if body.chars().count() > MAX_ANNOUNCEMENT_LEN {
+        return Err(Error::bad_request(format!(
+            "Announcement cannot be longer than {MAX_ANNOUNCEMENT_LEN} characters"
+        )));
+    }
+
+    let announcement = Announcement {
+        id: ObjectId::new(),
+        body: body.to_owned(),
+        created_at: DateTime::now(),
+    };
+    let announcement_doc =
+        bson::to_bson(&announcement).map_err(|err| Error::InternalServerError(err.to_string()))?;
+
+    let events = audit::atomically(|| async {
+        let previous = Room::find_one_and_update_before(
+            doc! { "_id": room_id },
+            doc! { "$push": { "announcements": {
+                "$each": [announcement_doc.clone()],
+                "$slice": -(MAX_ANNOUNCEMENTS as i32),
+            } } },
+        )
+        .await?
+        .ok_or_else(|| Error::NotFound("Room not found".into()))?;
+
+        let mut room = previous.clone();
+        room.announcements.push(announcement.clone());
+        let excess = room.announcements.len().saturating_sub(MAX_ANNOUNCEMENTS);
+        room.announcements.drain(..excess);
+
+        audit::record(
+            actor,
+            vec![Change {
+                room_id,
+                question_id: None,
+                kind: EventKind::RoomAnnounced,
+                before: Some(&previous),
+                after: Some(&room),
+            }],
+        )
+        .await
+    })
+    .await?;
+    projection::broadcast(room_server, &events).await;
+
+    Ok(PublicAnnouncement::from(announcement))
+}
@@ -0,0 +1,294 @@
+use bson::doc;
+use tracing::{field, Span};
+use wither::bson::{oid::ObjectId, DateTime};
+
+use crate::{
+    errors::Error,
+    models::{
+        event::{Actor, Event, EventKind},
+        question::{Answer, PublicQuestion, Question, Reply},
+    },
+    rate_limit::{Action, RateLimiter},
+    server::RoomServerHandle,
+    services::{
+        audit::{self, Change},
+        projection,
+    },
+    utils::{markdown, models::ModelExt, participant::Participant, to_object_id::to_object_id},
+};
+
+const MAX_ANSWER_LEN: usize = 10_000;
+const MAX_REPLY_LEN: usize = 2_000;
+const MAX_REPLIES: usize = 100;
+
+/// Write the host's answer to a question, or rewrite it.
+///
+pub async fn set_answer(
+    room_server: &RoomServerHandle,
+    actor: &Actor,
+    question_id: &str,
+    body: String,
+) -> Result<PublicQuestion, Error> {
+    let question_id = parse_id(question_id)?;
+    let body = check_body(body, MAX_ANSWER_LEN, "Answer")?;
+    let answer = Answer {
+        html: markdown::render(&body),
+        body,
+        updated_at: DateTime::now(),
+    };
+    let answer_doc = to_bson(&answer)?;
+
+    let (question, events) = audit::atomically(|| async {
+        let previous = Question::find_one_and_update_before(
+            doc! { "_id": question_id },
+            doc! { "$set": { "answer": answer_doc.clone() } },
+        )
+        .await?
+        .ok_or_else(|| Error::NotFound("Question not found".into()))?;
+
+        let kind = if previous.answer.is_some() {
+            EventKind::AnswerUpdated
+        } else {
+            EventKind::AnswerCreated
+        };
+        let question = Question {
+            answer: Some(answer.clone()),
+            ..previous.clone()
+        };
+
+        let events = record(actor, kind, &previous, &question).await?;
+        Ok((question, events))
+    })
+    .await?;
+
+    Ok(publish(room_server, &events, question).await)
+}
+
+pub async fn delete_answer(
+    room_server: &RoomServerHandle,
+    actor: &Actor,
+    question_id: &str,
+) -> Result<PublicQuestion, Error> {
+    let question_id = parse_id(question_id)?;
+
+    let (question, events) = audit::atomically(|| async {
+        let previous = Question::find_one_and_update_before(
+            doc! { "_id": question_id, "answer": { "$exists": true } },
+            doc! { "$unset": { "answer": "" } },
+        )
+        .await?
+        .ok_or_else(|| Error::NotFound("Answer not found".into()))?;
+
+        let question = Question {
+            answer: None,
+            ..previous.clone()
+        };
+
+        let events = record(actor, EventKind::AnswerDeleted, &previous, &question).await?;
+        Ok((question, events))
+    })
+    .await?;
+
+    Ok(publish(room_server, &events, question).await)
+}
+
+/// Add a follow-up from a participant under a question.
+///
+pub async fn create_reply(
+    room_server: &RoomServerHandle,
+    rate_limiter: &RateLimiter,
+    participant: &Participant,
+    actor: &Actor,
+    question_id: &str,
+    body: String,
+) -> Result<PublicQuestion, Error> {
+    let question_id = parse_id(question_id)?;
+    let body = check_body(body, MAX_REPLY_LEN, "Reply")?;
+
+    // replies are written like questions, so they share their budget
+    rate_limiter.check(Action::Question, participant)?;
+
+    let reply = Reply {
+        id: ObjectId::new(),
+        participant_id: participant.id.clone(),
+        html: markdown::render(&body),
+        body,
+        edited: false,
+    };
+    let reply_doc = to_bson(&reply)?;
+
+    let (question, events) = audit::atomically(|| async {
+        // the filter on the last allowed position keeps the thread bounded without a read first
+        let last_reply = format!("replies.{}", MAX_REPLIES - 1);
+        let previous = Question::find_one_and_update_before(
+            doc! { "_id": question_id, last_reply: { "$exists": false } },
+            doc! { "$push": { "replies": reply_doc.clone() } },
+        )
+        .await?;
+
+        let previous = match previous {
+            Some(previous) => previous,
+            None if Question::find_by_id(&question_id).await?.is_some() => {
+                return Err(Error::bad_request(format!(
+                    "A question cannot have more than {MAX_REPLIES} replies"
+                )))
+            }
+            None => return Err(Error::NotFound("Question not found".into())),
+        };
+
+        let mut question = previous.clone();
+        question.replies.push(reply.clone());
+
+        let events = record(actor, EventKind::ReplyCreated, &previous, &question).await?;
+        Ok((question, events))
+    })
+    .await?;
+
+    Ok(publish(room_server, &events, question).await)
+}
+
+/// Rewrite a reply, only its author can.
+///
+pub async fn update_reply(
+    room_server: &RoomServerHandle,
+    participant: &Participant,
+    actor: &Actor,
+    question_id: &str,
+    reply_id: &str,
+    body: String,
+) -> Result<PublicQuestion, Error> {
+    let question_id = parse_id(question_id)?;
+    let reply_id = parse_id(reply_id)?;
+    let author = author(participant)?;
+    let body = check_body(body, MAX_REPLY_LEN, "Reply")?;
+    let html = markdown::render(&body);
+
+    let (question, events) = audit::atomically(|| async {
+        let previous = Question::find_one_and_update_before(
+            doc! {
+                "_id": question_id,
+                "replies": { "$elemMatch": { "_id": reply_id, "participant_id": author } },
+            },
+            doc! { "$set": {
+                "replies.$.body": &body,
+                "replies.$.html": &html,
+                "replies.$.edited": true,
+            } },
+        )
+        .await?
+        .ok_or_else(|| Error::NotFound("Reply not found".into()))?;
+
+        let mut question = previous.clone();
+        if let Some(reply) = question
+            .replies
+            .iter_mut()
+            .find(|reply| reply.id == reply_id)
+        {
+            reply.body = body.clone();
+            reply.html = html.clone();
+            reply.edited = true;
+        }
+
+        let events = record(actor, EventKind::ReplyUpdated, &previous, &question).await?;
+        Ok((question, events))
+    })
+    .await?;
+
+    Ok(publish(room_server, &events, question).await)
+}
+
+/// Remove a reply, only its author can.
+///
+pub async fn delete_reply(
+    room_server: &RoomServerHandle,
+    participant: &Participant,
+    actor: &Actor,
+    question_id: &str,
+    reply_id: &str,
+) -> Result<PublicQuestion, Error> {
+    let question_id = parse_id(question_id)?;
+    let reply_id = parse_id(reply_id)?;
+    let author = author(participant)?;
+
+    let (question, events) = audit::atomically(|| async {
+        let previous = Question::find_one_and_update_before(
+            doc! {
+                "_id": question_id,
+                "replies": { "$elemMatch": { "_id": reply_id, "participant_id": author } },
+            },
+            doc! { "$pull": { "replies": { "_id": reply_id } } },
+        )
+        .await?
+        .ok_or_else(|| Error::NotFound("Reply not found".into()))?;
+
+        let mut question = previous.clone();
+        question.replies.retain(|reply| reply.id != reply_id);
+
+        let events = record(actor, EventKind::ReplyDeleted, &previous, &question).await?;
+        Ok((question, events))
+    })
+    .await?;
+
+    Ok(publish(room_server, &events, question).await)
+}
+
+async fn record(
+    actor: &Actor,
+    kind: EventKind,
+    previous: &Question,
+    question: &Question,
+) -> Result<Vec<Event>, Error> {
+    audit::record(
+        actor,
+        vec![Change {
+            room_id: question.room_id,
+            question_id: question.id,
+            kind,
+            before: Some(previous),
+            after: Some(question),
+        }],
+    )
+    .await
+}
+
+async fn publish(
+    room_server: &RoomServerHandle,
+    events: &[Event],
+    question: Question,
+) -> PublicQuestion {
+    Span::current().record("room_id", field::display(question.room_id));
+    projection::broadcast(room_server, events).await;
+
+    PublicQuestion::from(question)
+}
+
+/// Replies are tied to the participant id, anonymous participants cannot change theirs.
+///
+fn author(participant: &Participant) -> Result<&str, Error> {
+    participant
+        .id
+        .as_deref()
+        .ok_or_else(|| Error::Forbidden("Only the author of a reply can change it".into()))
+}
+
+fn check_body(body: String, max_len: usize, what: &str) -> Result<String, Error> {
+    let body = body.trim();
+    if body.is_empty() {
+        return Err(Error::bad_request(format!("{what} cannot be empty")));
+    }
+    if body.chars().count() > max_len {
+        return Err(Error::bad_request(format!(
+            "{what} cannot be longer than {max_len} characters"
+        )));
+    }
+
+    Ok(body.to_owned())
+}
+
+fn parse_id(id: &str) -> Result<ObjectId, Error> {
+    to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))
+}
+
+fn to_bson<T: serde::Serialize>(value: &T) -> Result<bson::Bson, Error> {
+    bson::to_bson(value).map_err(|err| Error::InternalServerError(err.to_string()))
+}
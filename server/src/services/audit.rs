@@ -0,0 +1,78 @@
+use std::future::Future;
+
+use serde::Serialize;
+use wither::bson::{oid::ObjectId, DateTime};
+
+use crate::{
+    database,
+    errors::Error,
+    models::event::{Actor, Event, EventKind},
+    settings,
+    utils::models::ModelExt,
+};
+
+/// A mutation about to be appended to the event log.
+///
+pub struct Change<'a, T: Serialize> {
+    pub room_id: ObjectId,
+    pub question_id: Option<ObjectId>,
+    pub kind: EventKind,
+    pub before: Option<&'a T>,
+    pub after: Option<&'a T>,
+}
+
+impl<'a, T: Serialize> Change<'a, T> {
+    fn into_event(self, actor: &Actor, created_at: DateTime) -> Result<Event, bson::ser::Error> {
+        Ok(Event {
+            id: Some(ObjectId::new()),
+            room_id: self.room_id,
+            question_id: self.question_id,
+            kind: self.kind,
+            actor: actor.clone(),
+            before: self.before.map(bson::to_bson).transpose()?,
+            after: self.after.map(bson::to_bson).transpose()?,
+            created_at,
+        })
+    }
+}
+
+/// Append changes made by `actor` to the event log and return the recorded events.
+///
+/// Failing to store the events is only logged, unless the log is the source of truth
+/// (`events.sourced`): the error then rolls back the mutation recorded within [`atomically`].
+pub async fn record<T: Serialize>(
+    actor: &Actor,
+    changes: Vec<Change<'_, T>>,
+) -> Result<Vec<Event>, Error> {
+    let created_at = DateTime::now();
+    let events = changes
+        .into_iter()
+        .map(|change| change.into_event(actor, created_at))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| Error::InternalServerError(err.to_string()))?;
+
+    if let Err(err) = Event::create_many(events.clone()).await {
+        tracing::error!(error = %err, "failed to record events");
+        if settings::get().events.sourced {
+            return Err(err);
+        }
+    }
+
+    Ok(events)
+}
+
+/// Run a mutation and the [`record`] of it, which must both happen within `operations`.
+///
+/// When the log is the source of truth (`events.sourced`) they run in one transaction, retried
+/// as a whole, so neither is stored without the other.
+pub async fn atomically<T, F, Fut>(operations: F) -> Result<T, Error>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    if settings::get().events.sourced {
+        database::transaction(operations).await
+    } else {
+        operations().await
+    }
+}
@@ -0,0 +1,241 @@
+use actix_web::web::Bytes;
+use async_stream::try_stream;
+use bson::doc;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use wither::bson::oid::ObjectId;
+use wither::mongodb::options::FindOptions;
+
+use crate::{
+    errors::Error,
+    models::{
+        poll::PublicPoll,
+        question::{PublicQuestion, Question},
+        room::{PublicRoom, Room},
+    },
+    services::poll,
+    utils::models::ModelExt,
+};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+    Md,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Md => "text/markdown; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Md => "md",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonExportHead<'a> {
+    room: &'a PublicRoom,
+    polls: &'a [PublicPoll],
+}
+
+/// Look the room up and stream all of its questions in `format`, oldest first.
+///
+/// Questions are written as they are read from the database, so the export never holds the
+/// whole room in memory. Polls, of which rooms have few, are loaded up front and exported with
+/// their results in JSON and Markdown, CSV only lists questions.
+pub async fn export_room(
+    room_id: ObjectId,
+    format: ExportFormat,
+) -> Result<impl Stream<Item = Result<Bytes, Error>>, Error> {
+    let room = Room::find_by_id(&room_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Room not found".into()))?;
+    let room = PublicRoom::from(room);
+
+    let stream = match format {
+        ExportFormat::Json => json(room, poll::room_polls(room_id).await?).boxed_local(),
+        ExportFormat::Csv => csv(room).boxed_local(),
+        ExportFormat::Md => markdown(room, poll::room_polls(room_id).await?).boxed_local(),
+    };
+
+    Ok(stream)
+}
+
+async fn questions(
+    room_id: ObjectId,
+    answered: Option<bool>,
+) -> Result<impl Stream<Item = Result<PublicQuestion, Error>>, Error> {
+    let mut filter = doc! { "room_id": room_id, "hidden": { "$ne": true } };
+    if let Some(answered) = answered {
+        filter.insert("answered", answered);
+    }
+
+    // ids grow with creation time
+    let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+    let cursor = Question::find_cursor(filter, options).await?;
+
+    Ok(cursor.map(|question| question.map(PublicQuestion::from).map_err(Error::Wither)))
+}
+
+fn json(room: PublicRoom, polls: Vec<PublicPoll>) -> impl Stream<Item = Result<Bytes, Error>> {
+    try_stream! {
+        // the head is an object missing its closing brace, so the questions can follow it
+        let head = JsonExportHead { room: &room, polls: &polls };
+        let mut head = serde_json::to_vec(&head).map_err(internal)?;
+        head.pop();
+        head.extend_from_slice(br#","questions":["#);
+        yield Bytes::from(head);
+
+        let mut questions = Box::pin(questions(room.id, None).await?);
+        let mut first = true;
+        while let Some(question) = questions.next().await {
+            let mut chunk = if first { Vec::new() } else { vec![b','] };
+            serde_json::to_writer(&mut chunk, &question?).map_err(internal)?;
+            first = false;
+            yield Bytes::from(chunk);
+        }
+
+        yield Bytes::from_static(b"]}");
+    }
+}
+
+fn csv(room: PublicRoom) -> impl Stream<Item = Result<Bytes, Error>> {
+    try_stream! {
+        yield csv_record(["id", "created_at", "value", "reaction_count", "answered"])?;
+
+        let mut questions = Box::pin(questions(room.id, None).await?);
+        while let Some(question) = questions.next().await {
+            let question = question?;
+            yield csv_record([
+                question.id.to_hex(),
+                question.created_at.try_to_rfc3339_string().map_err(internal)?,
+                question.value,
+                question.reaction_count.to_string(),
+                question.answered.to_string(),
+            ])?;
+        }
+    }
+}
+
+fn csv_record<I, T>(record: I) -> Result<Bytes, Error>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(record).map_err(internal)?;
+    let buffer = writer.into_inner().map_err(internal)?;
+
+    Ok(Bytes::from(buffer))
+}
+
+fn markdown(room: PublicRoom, polls: Vec<PublicPoll>) -> impl Stream<Item = Result<Bytes, Error>> {
+    try_stream! {
+        yield Bytes::from(format!("# {}\n", escape_markdown(&room.name)));
+
+        for (title, answered) in [("Answered", true), ("Unanswered", false)] {
+            yield Bytes::from(format!("\n## {title}\n"));
+
+            let mut questions = Box::pin(questions(room.id, Some(answered)).await?);
+            let mut empty = true;
+            while let Some(question) = questions.next().await {
+                let question = question?;
+                empty = false;
+                yield Bytes::from(markdown_question(&question)?);
+            }
+
+            if empty {
+                yield Bytes::from_static(b"\n_No questions._\n");
+            }
+        }
+
+        if !polls.is_empty() {
+            yield Bytes::from_static(b"\n## Polls\n");
+            for poll in &polls {
+                yield Bytes::from(markdown_poll(poll));
+            }
+        }
+    }
+}
+
+fn markdown_poll(poll: &PublicPoll) -> String {
+    let voters = match poll.voters {
+        1 => "1 voter".to_owned(),
+        count => format!("{count} voters"),
+    };
+    let status = if poll.open { "open" } else { "closed" };
+
+    let mut section = format!(
+        "\n### {}\n\n_{voters} · {status}_\n\n",
+        escape_markdown(&poll.question)
+    );
+    for option in &poll.options {
+        section.push_str(&format!(
+            "- {}: {}\n",
+            escape_markdown(&option.text),
+            option.votes
+        ));
+    }
+
+    section
+}
+
+fn markdown_question(question: &PublicQuestion) -> Result<String, Error> {
+    let asked_at = question
+        .created_at
+        .try_to_rfc3339_string()
+        .map_err(internal)?;
+    let reactions = match question.reaction_count {
+        1 => "1 reaction".to_owned(),
+        count => format!("{count} reactions"),
+    };
+
+    // quoted so that whatever the participant typed cannot break the transcript structure
+    let value = question
+        .value
+        .lines()
+        .map(|line| format!("> {line}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut section = format!("\n{value}\n\n_{asked_at} · {reactions}_\n");
+    if let Some(answer) = &question.answer {
+        section.push_str(&format!("\n**Answer:**\n\n{}\n", answer.body));
+    }
+
+    Ok(section)
+}
+
+/// Escape what Markdown would read as formatting in a single line of user text, like a room name,
+/// so it cannot add headings, links or HTML to the transcript.
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\n' | '\r' => escaped.push(' '),
+            '\\' | '`' | '*' | '_' | '{' | '}' | '[' | ']' | '(' | ')' | '<' | '>' | '#' | '+'
+            | '-' | '.' | '!' | '|' | '~' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn internal(err: impl std::fmt::Display) -> Error {
+    Error::InternalServerError(format!("Failed to export room: {err}"))
+}
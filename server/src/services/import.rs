@@ -0,0 +1,253 @@
+use std::collections::{BTreeMap, HashSet};
+
+use bson::doc;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use wither::bson::oid::ObjectId;
+
+use crate::{
+    errors::Error,
+    models::{
+        event::{Actor, EventKind},
+        question::Question,
+        room::{PublicRoom, Room},
+    },
+    services::audit::{self, Change},
+    settings,
+    utils::models::{validation_messages, ModelExt},
+};
+
+/// The room an import creates, found again by `key` when the import runs another time.
+///
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoomImport {
+    pub key: String,
+    pub name: String,
+    pub slow_mode_secs: Option<u32>,
+}
+
+/// A question to import, one CSV row or one element of the JSON `questions` array.
+///
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuestionRow {
+    pub value: String,
+    #[serde(default)]
+    pub answered: bool,
+    #[serde(default)]
+    pub reaction_count: u16,
+    /// Defaults to the lowercased text, so the same question is never imported twice.
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+/// Rows as parsed from the input, those that could not be parsed carry the reason.
+///
+pub type Rows = Vec<Result<QuestionRow, String>>;
+
+#[derive(Debug, Deserialize)]
+struct JsonImport {
+    #[serde(flatten)]
+    room: RoomImport,
+    questions: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RowError {
+    /// Position of the row in the input, starting at 1.
+    pub row: usize,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub room: PublicRoom,
+    pub room_created: bool,
+    pub created: usize,
+    pub skipped: usize,
+}
+
+/// Parse a JSON document holding the room fields and a `questions` array.
+///
+pub fn parse_json(data: &[u8]) -> Result<(RoomImport, Rows), Error> {
+    let import: JsonImport = serde_json::from_slice(data)
+        .map_err(|err| Error::bad_request(format!("Invalid import: {err}")))?;
+
+    let rows = import
+        .questions
+        .into_iter()
+        .map(|row| serde_json::from_value(row).map_err(|err| err.to_string()))
+        .collect();
+
+    Ok((import.room, rows))
+}
+
+/// Parse CSV with a header row naming the [`QuestionRow`] columns, only `value` is required.
+///
+pub fn parse_csv(data: &[u8]) -> Result<Rows, Error> {
+    let mut reader = csv::Reader::from_reader(data);
+
+    let headers = reader
+        .headers()
+        .map_err(|err| Error::bad_request(format!("Invalid import: {err}")))?;
+    if !headers.iter().any(|header| header == "value") {
+        return Err(Error::bad_request(
+            "Invalid import: missing a `value` column".into(),
+        ));
+    }
+
+    Ok(reader
+        .deserialize()
+        .map(|row| row.map_err(|err| err.to_string()))
+        .collect())
+}
+
+/// Create the room and its questions, or complete them when the import was run before.
+///
+/// Every row is checked before anything is written: if one is invalid, nothing is imported
+/// and the errors of all rows are reported. Questions already imported into the room, by key,
+/// are skipped, so fixing the input and running it again is safe.
+pub async fn import(
+    actor: &Actor,
+    room_import: RoomImport,
+    rows: Rows,
+) -> Result<ImportReport, Error> {
+    let key = room_import.key.trim().to_owned();
+    if key.is_empty() {
+        return Err(Error::bad_request("Import key cannot be empty".into()));
+    }
+
+    let existing_room = Room::find_one(doc! { "import_key": &key }).await?;
+    // a new room gets its id up front so the questions can be checked before it is created
+    let room_id = existing_room
+        .as_ref()
+        .and_then(|room| room.id)
+        .unwrap_or_else(ObjectId::new);
+
+    let mut questions = Vec::with_capacity(rows.len());
+    let mut row_errors = Vec::new();
+    for (index, row) in rows.into_iter().enumerate() {
+        let errors = match row.map(|row| question_from_row(room_id, row)) {
+            Ok(question) => match question.validate() {
+                Ok(()) => {
+                    questions.push(question);
+                    continue;
+                }
+                Err(errors) => validation_messages(&errors),
+            },
+            Err(err) => vec![err],
+        };
+
+        row_errors.push(RowError {
+            row: index + 1,
+            errors,
+        });
+    }
+
+    if !row_errors.is_empty() {
+        return Err(Error::InvalidImport { rows: row_errors });
+    }
+
+    let (room, room_created) = match existing_room {
+        Some(room) => (room, false),
+        None => {
+            let slow_mode_secs = room_import
+                .slow_mode_secs
+                .unwrap_or(settings::get().rate_limit.default_slow_mode_secs);
+            let mut room = Room::new(room_import.name, slow_mode_secs);
+            room.id = Some(room_id);
+            room.import_key = Some(key);
+            let room = audit::atomically(|| async {
+                let room = Room::create(room.clone()).await?;
+                audit::record(
+                    actor,
+                    vec![Change {
+                        room_id,
+                        question_id: None,
+                        kind: EventKind::RoomCreated,
+                        before: None,
+                        after: Some(&room),
+                    }],
+                )
+                .await?;
+                Ok(room)
+            })
+            .await?;
+
+            (room, true)
+        }
+    };
+
+    let (existing, _count) = Question::find_and_count(
+        doc! { "room_id": room_id, "import_key": { "$exists": true } },
+        None,
+    )
+    .await?;
+    let mut seen = existing
+        .into_iter()
+        .filter_map(|question| question.import_key)
+        .collect::<HashSet<_>>();
+
+    let total = questions.len();
+    let questions = questions
+        .into_iter()
+        .filter(|question| seen.insert(question.import_key.clone().unwrap_or_default()))
+        .collect::<Vec<_>>();
+
+    let created = audit::atomically(|| async {
+        let created = Question::create_many(questions.clone()).await?;
+
+        let changes = questions
+            .iter()
+            .map(|question| Change {
+                room_id,
+                question_id: question.id,
+                kind: EventKind::QuestionCreated,
+                before: None,
+                after: Some(question),
+            })
+            .collect();
+        audit::record(actor, changes).await?;
+        Ok(created)
+    })
+    .await?;
+
+    Ok(ImportReport {
+        room: PublicRoom::from(room),
+        room_created,
+        created,
+        skipped: total - created,
+    })
+}
+
+fn question_from_row(room_id: ObjectId, row: QuestionRow) -> Question {
+    let import_key = row
+        .key
+        .map(|key| key.trim().to_owned())
+        .filter(|key| !key.is_empty())
+        // not `normalize`d, that drops stop words and would merge distinct questions
+        .unwrap_or_else(|| row.value.trim().to_lowercase());
+
+    let mut question = Question {
+        // set up front so the recorded events can point at the questions
+        id: Some(ObjectId::new()),
+        room_id,
+        answered: row.answered,
+        reaction_count: row.reaction_count,
+        // imported counts predate reaction kinds
+        reactions: BTreeMap::new(),
+        downvotes: 0,
+        confidence: 0.0,
+        hidden: false,
+        value: row.value.trim().to_owned(),
+        display_name: None,
+        anonymous: false,
+        participant_id: None,
+        import_key: Some(import_key),
+        answer: None,
+        replies: Vec::new(),
+        reaction_milestones: Vec::new(),
+    };
+    question.rescore(None);
+
+    question
+}
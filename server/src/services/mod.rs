@@ -0,0 +1,11 @@
+pub mod announcement;
+pub mod answer;
+pub mod audit;
+pub mod export;
+pub mod import;
+pub mod poll;
+pub mod projection;
+pub mod question;
+pub mod search;
+pub mod snapshot;
+pub mod spotlight;
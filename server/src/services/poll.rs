@@ -0,0 +1,208 @@
+use bson::doc;
+use wither::bson::oid::ObjectId;
+use wither::mongodb::options::FindOptions;
+
+use crate::{
+    errors::Error,
+    models::{
+        event::{Actor, Event, EventKind},
+        poll::{Poll, PollVote, PublicPoll},
+        room::Room,
+    },
+    server::RoomServerHandle,
+    services::{
+        audit::{self, Change},
+        projection,
+    },
+    utils::{models::ModelExt, participant::Participant, to_object_id::to_object_id},
+};
+
+/// Open a poll in a room and broadcast it.
+///
+pub async fn create_poll(
+    room_server: &RoomServerHandle,
+    actor: &Actor,
+    room_id: &str,
+    question: String,
+    options: Vec<String>,
+    multiple: bool,
+) -> Result<PublicPoll, Error> {
+    let room_id = parse_id(room_id)?;
+    let room = Room::find_by_id(&room_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Room not found".into()))?;
+
+    if room.closed {
+        return Err(Error::bad_request("Room is closed".into()));
+    }
+
+    let options = options
+        .into_iter()
+        .map(|option| option.trim().to_owned())
+        .collect();
+    let poll = Poll::new(room_id, question.trim().to_owned(), options, multiple);
+    let (poll, events) = audit::atomically(|| async {
+        let poll = Poll::create(poll.clone()).await?;
+        let events = record(actor, EventKind::PollCreated, None, &poll).await?;
+        Ok((poll, events))
+    })
+    .await?;
+    projection::broadcast(room_server, &events).await;
+
+    Ok(PublicPoll::from(poll))
+}
+
+/// Polls of a room, oldest first.
+///
+pub async fn room_polls(room_id: ObjectId) -> Result<Vec<PublicPoll>, Error> {
+    let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+    let (polls, _count) = Poll::find_and_count(doc! { "room_id": room_id }, options).await?;
+
+    Ok(polls.into_iter().map(Into::into).collect())
+}
+
+/// Open or close a poll for votes.
+///
+pub async fn set_open(
+    room_server: &RoomServerHandle,
+    actor: &Actor,
+    poll_id: &str,
+    open: bool,
+) -> Result<PublicPoll, Error> {
+    let poll_id = parse_id(poll_id)?;
+
+    let (poll, events) = audit::atomically(|| async {
+        let previous = Poll::find_one_and_update_before(
+            doc! { "_id": poll_id },
+            doc! { "$set": { "open": open } },
+        )
+        .await?
+        .ok_or_else(|| Error::NotFound("Poll not found".into()))?;
+
+        let poll = Poll {
+            open,
+            ..previous.clone()
+        };
+        if previous.open == open {
+            return Ok((poll, Vec::new()));
+        }
+
+        let kind = if open {
+            EventKind::PollOpened
+        } else {
+            EventKind::PollClosed
+        };
+        let events = record(actor, kind, Some(&previous), &poll).await?;
+        Ok((poll, events))
+    })
+    .await?;
+    projection::broadcast(room_server, &events).await;
+
+    Ok(PublicPoll::from(poll))
+}
+
+/// Count the vote of a participant, who can only vote once per poll.
+///
+/// `choices` are positions of options, exactly one for single-choice polls.
+pub async fn vote(
+    room_server: &RoomServerHandle,
+    participant: &Participant,
+    actor: &Actor,
+    poll_id: &str,
+    mut choices: Vec<u32>,
+) -> Result<PublicPoll, Error> {
+    let poll_id = parse_id(poll_id)?;
+    let participant_id = participant
+        .id
+        .clone()
+        .ok_or_else(|| Error::bad_request("A participant id is required to vote".into()))?;
+
+    let poll = Poll::find_by_id(&poll_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Poll not found".into()))?;
+
+    choices.sort_unstable();
+    choices.dedup();
+    if choices.is_empty() || (!poll.multiple && choices.len() > 1) {
+        let message = if poll.multiple {
+            "Pick at least one option"
+        } else {
+            "Pick exactly one option"
+        };
+        return Err(Error::bad_request(message.into()));
+    }
+    if choices
+        .iter()
+        .any(|&choice| choice as usize >= poll.options.len())
+    {
+        return Err(Error::bad_request("Unknown poll option".into()));
+    }
+
+    let mut increments = doc! { "voters": 1 };
+    for choice in &choices {
+        increments.insert(format!("options.{choice}.votes"), 1);
+    }
+
+    let (poll, events) = audit::atomically(|| async {
+        // the unique index on the vote settles concurrent votes of the same participant
+        PollVote::create(PollVote {
+            id: None,
+            poll_id,
+            participant_id: participant_id.clone(),
+            choices: choices.clone(),
+        })
+        .await
+        .map_err(|err| match err {
+            Error::Conflict(_) => Error::Conflict("You already voted in this poll".into()),
+            err => err,
+        })?;
+
+        // counted only while the poll is open, however late it was closed
+        let previous = Poll::find_one_and_update_before(
+            doc! { "_id": poll_id, "open": true },
+            doc! { "$inc": increments.clone() },
+        )
+        .await?;
+        let Some(previous) = previous else {
+            PollVote::delete_many(doc! { "poll_id": poll_id, "participant_id": &participant_id })
+                .await?;
+            return Err(Error::bad_request("Poll is closed".into()));
+        };
+
+        let mut poll = previous.clone();
+        poll.voters += 1;
+        for &choice in &choices {
+            poll.options[choice as usize].votes += 1;
+        }
+
+        let events = record(actor, EventKind::PollVoted, Some(&previous), &poll).await?;
+        Ok((poll, events))
+    })
+    .await?;
+    projection::broadcast(room_server, &events).await;
+
+    Ok(PublicPoll::from(poll))
+}
+
+async fn record(
+    actor: &Actor,
+    kind: EventKind,
+    previous: Option<&Poll>,
+    poll: &Poll,
+) -> Result<Vec<Event>, Error> {
+    audit::record(
+        actor,
+        vec![Change {
+            room_id: poll.room_id,
+            question_id: None,
+            kind,
+            before: previous,
+            after: Some(poll),
+        }],
+    )
+    .await
+}
+
+fn parse_id(id: &str) -> Result<ObjectId, Error> {
+    to_object_id(id).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))
+}
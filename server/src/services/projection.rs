@@ -0,0 +1,456 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use bson::serde_helpers::bson_datetime_as_rfc3339_string;
+use futures::TryStreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+use wither::bson::{doc, oid::ObjectId, Bson, DateTime};
+use wither::mongodb::options::FindOptions;
+
+use crate::{
+    errors::Error,
+    models::{
+        event::{Event, EventKind},
+        poll::{Poll, PublicPoll},
+        question::{MergedQuestion, PublicQuestion, Question, ReactionCounts, Reply},
+        room::{PublicAnnouncement, PublicRoom, Room},
+    },
+    server::RoomServerHandle,
+    services::spotlight,
+    settings,
+    utils::{message_data::MessageData, models::ModelExt},
+};
+
+/// A room, its questions and polls as rebuilt from the event log.
+///
+#[derive(Debug, Clone)]
+pub struct Board {
+    pub room: Room,
+    pub questions: BTreeMap<ObjectId, Question>,
+    pub polls: BTreeMap<ObjectId, Poll>,
+}
+
+/// What a room looked like at a point in time.
+///
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    #[serde(with = "bson_datetime_as_rfc3339_string")]
+    pub at: DateTime,
+    pub room: PublicRoom,
+    pub questions: Vec<PublicQuestion>,
+    pub polls: Vec<PublicPoll>,
+}
+
+impl Snapshot {
+    pub fn new(board: Board, at: DateTime) -> Self {
+        Self {
+            at,
+            room: PublicRoom::from(board.room),
+            questions: board.questions.into_values().map(Into::into).collect(),
+            polls: board.polls.into_values().map(Into::into).collect(),
+        }
+    }
+}
+
+/// A rebuilt room, with the stored questions and polls missing from its event log.
+///
+/// Those are left as they are for a host to look into, the log may just be incomplete.
+#[derive(Debug, Serialize)]
+pub struct Rebuild {
+    #[serde(flatten)]
+    pub snapshot: Snapshot,
+    pub unrecorded_questions: Vec<String>,
+    pub unrecorded_polls: Vec<String>,
+}
+
+/// Replay the events of a room recorded up to `until`, or all of them.
+///
+/// Returns `None` when the creation of the room is not in the log, e.g. for rooms created
+/// before events were recorded.
+pub async fn replay(room_id: ObjectId, until: Option<DateTime>) -> Result<Option<Board>, Error> {
+    let mut filter = doc! { "room_id": room_id };
+    if let Some(until) = until {
+        filter.insert("created_at", doc! { "$lte": until });
+    }
+    let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+
+    let mut events = Event::find_cursor(filter, options).await?;
+    let mut board = None;
+    while let Some(event) = events.try_next().await.map_err(Error::Wither)? {
+        apply(&mut board, &event)?;
+    }
+
+    Ok(board)
+}
+
+/// Overwrite the stored room, questions and polls with the ones replayed from the event log.
+///
+/// Stored questions and polls the log knows nothing about are reported, never deleted.
+pub async fn rebuild(room_id: ObjectId) -> Result<Rebuild, Error> {
+    let board = replay(room_id, None)
+        .await?
+        .ok_or_else(|| Error::NotFound("Room has no recorded history".into()))?;
+
+    Room::upsert(&board.room).await?;
+
+    let ids = board.questions.keys().collect::<Vec<_>>();
+    let (unrecorded_questions, _count) =
+        Question::find_and_count(doc! { "room_id": room_id, "_id": { "$nin": ids } }, None).await?;
+    for question in board.questions.values() {
+        Question::upsert(question).await?;
+    }
+
+    let ids = board.polls.keys().collect::<Vec<_>>();
+    let (unrecorded_polls, _count) =
+        Poll::find_and_count(doc! { "room_id": room_id, "_id": { "$nin": ids } }, None).await?;
+    for poll in board.polls.values() {
+        Poll::upsert(poll).await?;
+    }
+
+    let unrecorded_questions =
+        unrecorded_ids(unrecorded_questions.iter().map(|question| question.id));
+    let unrecorded_polls = unrecorded_ids(unrecorded_polls.iter().map(|poll| poll.id));
+    if !unrecorded_questions.is_empty() || !unrecorded_polls.is_empty() {
+        tracing::warn!(
+            %room_id,
+            questions = ?unrecorded_questions,
+            polls = ?unrecorded_polls,
+            "stored documents are missing from the event log"
+        );
+    }
+
+    tracing::info!(%room_id, questions = board.questions.len(), "rebuilt room from events");
+
+    Ok(Rebuild {
+        snapshot: Snapshot::new(board, DateTime::now()),
+        unrecorded_questions,
+        unrecorded_polls,
+    })
+}
+
+fn unrecorded_ids(ids: impl Iterator<Item = Option<ObjectId>>) -> Vec<String> {
+    ids.flatten().map(|id| id.to_hex()).collect()
+}
+
+/// Fold an event into the board, `None` until the room is created.
+///
+/// Reactions and votes are applied as the difference between `before` and `after`, so
+/// concurrent ones add up whatever order their events were stored in.
+fn apply(board: &mut Option<Board>, event: &Event) -> Result<(), Error> {
+    if event.kind == EventKind::RoomCreated {
+        if let Some(room) = decode::<Room>(&event.after)? {
+            *board = Some(Board {
+                room,
+                questions: BTreeMap::new(),
+                polls: BTreeMap::new(),
+            });
+        }
+        return Ok(());
+    }
+
+    let Some(board) = board else {
+        tracing::warn!(event_id = ?event.id, "skipping event recorded before its room");
+        return Ok(());
+    };
+
+    match event.kind {
+        EventKind::RoomClosed => {
+            board.room.closed = true;
+            return Ok(());
+        }
+        EventKind::RoomSpotlighted => {
+            board.room.spotlight = event.question_id;
+            return Ok(());
+        }
+        EventKind::RoomAnnounced => {
+            if let Some(room) = decode::<Room>(&event.after)? {
+                board.room.announcements = room.announcements;
+            }
+            return Ok(());
+        }
+        EventKind::PollCreated
+        | EventKind::PollOpened
+        | EventKind::PollClosed
+        | EventKind::PollVoted => return apply_poll(board, event),
+        _ => {}
+    }
+
+    let Some(question_id) = event.question_id else {
+        return Ok(());
+    };
+
+    match event.kind {
+        EventKind::QuestionCreated => {
+            if let Some(question) = decode::<Question>(&event.after)? {
+                board.questions.insert(question_id, question);
+            }
+        }
+        EventKind::QuestionMerged if event.after.is_none() => {
+            board.questions.remove(&question_id);
+        }
+        kind => {
+            let Some(question) = board.questions.get_mut(&question_id) else {
+                return Ok(());
+            };
+
+            match kind {
+                EventKind::QuestionAnswered => question.answered = true,
+                EventKind::QuestionUnanswered => question.answered = false,
+                EventKind::QuestionReacted
+                | EventKind::QuestionDownvoted
+                | EventKind::QuestionMerged => {
+                    let before = decode::<Question>(&event.before)?;
+                    let after = decode::<Question>(&event.after)?;
+                    apply_votes(question, before.as_ref(), after.as_ref());
+                    question.rescore(board.room.hide_below);
+                }
+                EventKind::AnswerCreated | EventKind::AnswerUpdated | EventKind::AnswerDeleted => {
+                    question.answer =
+                        decode::<Question>(&event.after)?.and_then(|after| after.answer);
+                }
+                EventKind::ReplyCreated | EventKind::ReplyUpdated | EventKind::ReplyDeleted => {
+                    let before = decode::<Question>(&event.before)?
+                        .map_or_else(Vec::new, |before| before.replies);
+                    let after = decode::<Question>(&event.after)?
+                        .map_or_else(Vec::new, |after| after.replies);
+                    apply_replies(question, &before, after);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_poll(board: &mut Board, event: &Event) -> Result<(), Error> {
+    let Some(after) = decode::<Poll>(&event.after)? else {
+        return Ok(());
+    };
+    let Some(poll_id) = after.id else {
+        return Ok(());
+    };
+
+    if event.kind == EventKind::PollCreated {
+        board.polls.insert(poll_id, after);
+        return Ok(());
+    }
+
+    let Some(poll) = board.polls.get_mut(&poll_id) else {
+        return Ok(());
+    };
+
+    match event.kind {
+        EventKind::PollOpened => poll.open = true,
+        EventKind::PollClosed => poll.open = false,
+        EventKind::PollVoted => {
+            let before = decode::<Poll>(&event.before)?;
+            let votes = |poll: Option<&Poll>, position: usize| {
+                poll.and_then(|poll| poll.options.get(position))
+                    .map_or(0, |option| option.votes)
+            };
+
+            poll.voters = (poll.voters + after.voters)
+                .saturating_sub(before.as_ref().map_or(0, |before| before.voters));
+            for (position, option) in poll.options.iter_mut().enumerate() {
+                option.votes = (option.votes + votes(Some(&after), position))
+                    .saturating_sub(votes(before.as_ref(), position));
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Add the difference in reactions and downvotes between `before` and `after`, in total and
+/// for each kind.
+fn apply_votes(question: &mut Question, before: Option<&Question>, after: Option<&Question>) {
+    let add = |count: u16, before: u16, after: u16| {
+        (i32::from(count) + i32::from(after) - i32::from(before)).clamp(0, i32::from(u16::MAX))
+            as u16
+    };
+    let total =
+        |question: Option<&Question>| question.map_or(0, |question| question.reaction_count);
+    let count = |question: Option<&Question>, kind: &str| {
+        question
+            .and_then(|question| question.reactions.get(kind))
+            .copied()
+            .unwrap_or(0)
+    };
+
+    question.reaction_count = add(question.reaction_count, total(before), total(after));
+    question.downvotes = add(
+        question.downvotes,
+        before.map_or(0, |before| before.downvotes),
+        after.map_or(0, |after| after.downvotes),
+    );
+
+    let kinds = [before, after]
+        .into_iter()
+        .flatten()
+        .flat_map(|question| question.reactions.keys().cloned())
+        .collect::<BTreeSet<_>>();
+    for kind in kinds {
+        let current = question.reactions.entry(kind.clone()).or_default();
+        *current = add(*current, count(before, &kind), count(after, &kind));
+    }
+}
+
+/// Apply the replies that differ between `before` and `after`, leaving the ones added or removed
+/// by concurrent events alone.
+fn apply_replies(question: &mut Question, before: &[Reply], after: Vec<Reply>) {
+    question.replies.retain(|reply| {
+        !before.iter().any(|removed| removed.id == reply.id)
+            || after.iter().any(|kept| kept.id == reply.id)
+    });
+
+    for reply in after {
+        if before.contains(&reply) {
+            continue;
+        }
+        match question
+            .replies
+            .iter_mut()
+            .find(|existing| existing.id == reply.id)
+        {
+            Some(existing) => *existing = reply,
+            None => question.replies.push(reply),
+        }
+    }
+}
+
+/// Send subscribers of the room what the events changed.
+///
+/// Does nothing when change streams are enabled, the watcher sends the changes then.
+pub async fn broadcast(room_server: &RoomServerHandle, events: &[Event]) {
+    if settings::get().change_streams.enabled {
+        return;
+    }
+
+    // a merge is recorded as the removal of one question and the update of another
+    let merged_from = events
+        .iter()
+        .find(|event| event.kind == EventKind::QuestionMerged && event.after.is_none())
+        .and_then(|event| event.question_id);
+
+    for event in events {
+        if let Err(err) = send(room_server, event, merged_from).await {
+            tracing::error!(error = %err, event_id = ?event.id, "failed to broadcast event");
+        }
+    }
+}
+
+async fn send(
+    room_server: &RoomServerHandle,
+    event: &Event,
+    merged_from: Option<ObjectId>,
+) -> Result<(), Error> {
+    let room_id = event.room_id.to_string();
+
+    match event.kind {
+        EventKind::RoomCreated => {}
+        EventKind::RoomClosed => {
+            if let Some(room) = decode::<Room>(&event.after)? {
+                let public_room = PublicRoom::from(room);
+                room_server
+                    .send_message(room_id, MessageData::close(&public_room))
+                    .await;
+            }
+        }
+        EventKind::RoomSpotlighted => {
+            if let Some(room) = decode::<Room>(&event.after)? {
+                spotlight::broadcast(room_server, &room).await?;
+            }
+        }
+        EventKind::RoomAnnounced => {
+            let announcement =
+                decode::<Room>(&event.after)?.and_then(|room| room.announcements.last().cloned());
+            if let Some(announcement) = announcement {
+                let public_announcement = PublicAnnouncement::from(announcement);
+                room_server
+                    .send_message(room_id, MessageData::announcement(&public_announcement))
+                    .await;
+            }
+        }
+        EventKind::PollCreated
+        | EventKind::PollOpened
+        | EventKind::PollClosed
+        | EventKind::PollVoted => {
+            if let Some(poll) = decode::<Poll>(&event.after)? {
+                let public_poll = PublicPoll::from(poll);
+                room_server
+                    .send_message(room_id, MessageData::poll(&public_poll))
+                    .await;
+            }
+        }
+        EventKind::QuestionCreated => {
+            if let Some(question) = decode::<Question>(&event.after)? {
+                let involves = question.involves();
+                let public_question = PublicQuestion::from(question);
+                room_server
+                    .send_message_involving(
+                        room_id,
+                        MessageData::create(&public_question),
+                        involves,
+                    )
+                    .await;
+            }
+        }
+        EventKind::QuestionReacted | EventKind::QuestionDownvoted => {
+            let before = decode::<Question>(&event.before)?;
+            let after = decode::<Question>(&event.after)?;
+            if let (Some(before), Some(after)) = (before, after) {
+                if let Some(counts) = ReactionCounts::changed(&before, &after) {
+                    room_server.send_coalesced(
+                        room_id,
+                        counts.question_id.to_hex(),
+                        MessageData::reaction(&counts),
+                        after.involves(),
+                    );
+                }
+            }
+        }
+        EventKind::QuestionAnswered
+        | EventKind::QuestionUnanswered
+        | EventKind::AnswerCreated
+        | EventKind::AnswerUpdated
+        | EventKind::AnswerDeleted
+        | EventKind::ReplyCreated
+        | EventKind::ReplyUpdated
+        | EventKind::ReplyDeleted => {
+            if let Some(question) = decode::<Question>(&event.after)? {
+                let involves = question.involves();
+                let public_question = PublicQuestion::from(question);
+                room_server
+                    .send_message_involving(
+                        room_id,
+                        MessageData::update(&public_question),
+                        involves,
+                    )
+                    .await;
+            }
+        }
+        EventKind::QuestionMerged => {
+            if let (Some(question), Some(from)) = (decode::<Question>(&event.after)?, merged_from) {
+                let involves = question.involves();
+                let merged_question = MergedQuestion {
+                    from,
+                    into: PublicQuestion::from(question),
+                };
+                room_server
+                    .send_message_involving(room_id, MessageData::merge(&merged_question), involves)
+                    .await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn decode<T: DeserializeOwned>(value: &Option<Bson>) -> Result<Option<T>, Error> {
+    value
+        .clone()
+        .map(bson::from_bson)
+        .transpose()
+        .map_err(|err| Error::InternalServerError(format!("Failed to decode event: {err}")))
+}
@@ -0,0 +1,840 @@
+use std::collections::BTreeMap;
+
+use bson::{bson, doc};
+use tracing::{field, Span};
+use wither::bson::oid::ObjectId;
+use wither::mongodb::options::FindOptions;
+
+use crate::{
+    errors::Error,
+    models::{
+        event::{Actor, Event, EventKind, Source},
+        question::{
+            ParticipantVotes, PublicQuestion, Question, QuestionAuthor, QuestionDownvote,
+            QuestionReaction, SimilarQuestion, ToggledDownvote, ToggledReaction,
+        },
+        room::{NameMode, Room},
+        webhook::WebhookEvent,
+    },
+    rate_limit::{Action, RateLimiter},
+    reaction_buffer::ReactionBuffer,
+    server::RoomServerHandle,
+    services::{
+        audit::{self, Change},
+        projection,
+    },
+    settings,
+    utils::{
+        models::ModelExt, participant::Participant, similarity::Fingerprint,
+        to_object_id::to_object_id,
+    },
+    webhooks,
+};
+
+const MAX_DISPLAY_NAME_LEN: usize = 50;
+
+/// What a participant asks.
+///
+pub struct NewQuestion {
+    pub value: String,
+    /// Signs the question, in rooms that allow names.
+    pub display_name: Option<String>,
+    /// Post even when similar questions exist.
+    pub force: bool,
+}
+
+/// Ask a question in a room and broadcast it to the room's subscribers.
+///
+/// Unless `force` is set, the question is refused with suggestions when the room already has
+/// similar ones, so the participant can react to those instead.
+pub async fn create_question(
+    room_server: &RoomServerHandle,
+    rate_limiter: &RateLimiter,
+    participant: &Participant,
+    source: Source,
+    room_id: &str,
+    new_question: NewQuestion,
+) -> Result<PublicQuestion, Error> {
+    let NewQuestion {
+        value,
+        display_name,
+        force,
+    } = new_question;
+
+    let room_id = to_object_id(room_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let room = Room::find_by_id(&room_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Room not found".into()))?;
+
+    if room.closed {
+        return Err(Error::bad_request("Room is closed".into()));
+    }
+
+    let display_name = check_display_name(room.names, display_name)?;
+    let anonymous = room.names == NameMode::Anonymous;
+
+    if !force {
+        let suggestions = find_similar(&room_id, &value).await?;
+        if !suggestions.is_empty() {
+            return Err(Error::DuplicateQuestion { suggestions });
+        }
+    }
+
+    // only a question that is going to be asked costs a token
+    rate_limiter.check_slow_mode(&room_id, room.slow_mode_secs, participant)?;
+    rate_limiter.check(Action::Question, participant)?;
+
+    let mut question = Question::new(room_id, value);
+    question.display_name = display_name;
+    question.anonymous = anonymous;
+    question.participant_id = participant.id.clone();
+    let actor = Actor::new(participant, source);
+    let (question, events) = audit::atomically(|| async {
+        let question = Question::create(question.clone()).await?;
+        let events = audit::record(
+            &actor,
+            vec![Change {
+                room_id,
+                question_id: question.id,
+                kind: EventKind::QuestionCreated,
+                before: None,
+                after: Some(&question),
+            }],
+        )
+        .await?;
+        Ok((question, events))
+    })
+    .await?;
+    projection::broadcast(room_server, &events).await;
+
+    let public_question = PublicQuestion::from(question);
+    Span::current().record("question_id", field::display(public_question.id));
+
+    webhooks::emit(room_id, WebhookEvent::QuestionCreated, &public_question);
+
+    Ok(public_question)
+}
+
+/// The trimmed name, checked against what the room asks for.
+///
+/// Names sent to anonymous rooms are kept for hosts, see [`author`].
+fn check_display_name(
+    names: NameMode,
+    display_name: Option<String>,
+) -> Result<Option<String>, Error> {
+    let display_name = display_name
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty());
+
+    if names == NameMode::Required && display_name.is_none() {
+        return Err(Error::bad_request(
+            "A display name is required in this room".into(),
+        ));
+    }
+
+    if display_name
+        .as_ref()
+        .is_some_and(|name| name.chars().count() > MAX_DISPLAY_NAME_LEN)
+    {
+        return Err(Error::bad_request(format!(
+            "Display name cannot be longer than {MAX_DISPLAY_NAME_LEN} characters"
+        )));
+    }
+
+    Ok(display_name)
+}
+
+/// The questions a participant asked in a room, in the room's order.
+///
+/// Hidden questions are included, their author still wants to find them.
+pub async fn asked_by(
+    participant: &Participant,
+    room_id: &str,
+) -> Result<Vec<PublicQuestion>, Error> {
+    let participant_id = participant_id(participant)?;
+    let room = find_room(room_id).await?;
+
+    let options = FindOptions::builder().sort(room.question_sort()).build();
+    let (questions, _count) = Question::find_and_count(
+        doc! { "room_id": room.id, "participant_id": participant_id },
+        options,
+    )
+    .await?;
+
+    Ok(questions.into_iter().map(Into::into).collect())
+}
+
+/// The questions of a room a participant reacted to or downvoted, in the room's order.
+///
+pub async fn voted_by(
+    participant: &Participant,
+    room_id: &str,
+) -> Result<Vec<ParticipantVotes>, Error> {
+    let participant_id = participant_id(participant)?;
+    let room = find_room(room_id).await?;
+
+    let (reactions, _count) = QuestionReaction::find_and_count(
+        doc! { "participant_id": &participant_id },
+        FindOptions::default(),
+    )
+    .await?;
+    let (downvotes, _count) = QuestionDownvote::find_and_count(
+        doc! { "participant_id": &participant_id },
+        FindOptions::default(),
+    )
+    .await?;
+
+    let mut kinds = BTreeMap::<ObjectId, Vec<String>>::new();
+    for reaction in reactions {
+        kinds
+            .entry(reaction.question_id)
+            .or_default()
+            .push(reaction.kind);
+    }
+    let downvoted = downvotes
+        .into_iter()
+        .map(|downvote| downvote.question_id)
+        .collect::<Vec<_>>();
+
+    let question_ids = kinds.keys().chain(&downvoted).copied().collect::<Vec<_>>();
+    if question_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // the votes span every room, the questions narrow them down to this one
+    let options = FindOptions::builder().sort(room.question_sort()).build();
+    let (questions, _count) = Question::find_and_count(
+        doc! { "room_id": room.id, "_id": { "$in": question_ids } },
+        options,
+    )
+    .await?;
+
+    Ok(questions
+        .into_iter()
+        .map(|question| {
+            let question_id = question.id.unwrap_or_default();
+            ParticipantVotes {
+                reactions: kinds.remove(&question_id).unwrap_or_default(),
+                downvoted: downvoted.contains(&question_id),
+                question: PublicQuestion::from(question),
+            }
+        })
+        .collect())
+}
+
+fn participant_id(participant: &Participant) -> Result<String, Error> {
+    participant
+        .id
+        .clone()
+        .ok_or_else(|| Error::bad_request("A participant id is required".into()))
+}
+
+async fn find_room(room_id: &str) -> Result<Room, Error> {
+    let room_id = to_object_id(room_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    Room::find_by_id(&room_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Room not found".into()))
+}
+
+/// Who asked a question, for hosts moderating the room.
+///
+pub async fn author(question_id: &str) -> Result<QuestionAuthor, Error> {
+    let question_id = to_object_id(question_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+    let question = Question::find_by_id(&question_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Question not found".into()))?;
+    Span::current().record("room_id", field::display(question.room_id));
+
+    let kind = bson::to_bson(&EventKind::QuestionCreated)
+        .map_err(|err| Error::InternalServerError(err.to_string()))?;
+    let actor = Event::find_one(doc! {
+        "room_id": question.room_id,
+        "question_id": question_id,
+        "kind": kind,
+    })
+    .await?
+    .map(|event| event.actor);
+
+    Ok(QuestionAuthor {
+        question_id,
+        display_name: question.display_name,
+        participant_id: question.participant_id.or_else(|| {
+            actor
+                .as_ref()
+                .and_then(|actor| actor.participant_id.clone())
+        }),
+        ip: actor.and_then(|actor| actor.ip),
+    })
+}
+
+/// Add the first reaction kind of the room to a question and broadcast the new count.
+///
+/// Anyone can add it any number of times, unlike the reactions toggled with
+/// [`toggle_reaction`].
+pub async fn react_question(
+    room_server: &RoomServerHandle,
+    rate_limiter: &RateLimiter,
+    reaction_buffer: &ReactionBuffer,
+    participant: &Participant,
+    source: Source,
+    question_id: &str,
+) -> Result<PublicQuestion, Error> {
+    let question_id = to_object_id(question_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    rate_limiter.check(Action::Reaction, participant)?;
+
+    let (mut question, room) = find_with_room(&question_id).await?;
+    let kind = room
+        .reaction_kinds
+        .first()
+        .ok_or_else(|| Error::bad_request("Room has no reactions".into()))?;
+
+    if reaction_buffer.enabled() {
+        // written and broadcast with the next flush, the caller sees the count it will have
+        for (kind, delta) in reaction_buffer.add(question_id, kind) {
+            question.reaction_count = question.reaction_count.saturating_add_signed(delta);
+            let count = question.reactions.entry(kind).or_default();
+            *count = count.saturating_add_signed(delta);
+        }
+        return Ok(PublicQuestion::from(question));
+    }
+
+    let actor = Actor::new(participant, source);
+    let question = add_vote(
+        room_server,
+        &actor,
+        &room,
+        question_id,
+        Vote::reaction(kind, 1),
+    )
+    .await?;
+
+    Ok(PublicQuestion::from(question))
+}
+
+/// Add a reaction of the participant to a question, or take it back when they already have it.
+///
+pub async fn toggle_reaction(
+    room_server: &RoomServerHandle,
+    rate_limiter: &RateLimiter,
+    participant: &Participant,
+    source: Source,
+    question_id: &str,
+    kind: &str,
+) -> Result<ToggledReaction, Error> {
+    let question_id = to_object_id(question_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+    let participant_id = participant
+        .id
+        .clone()
+        .ok_or_else(|| Error::bad_request("A participant id is required to react".into()))?;
+
+    rate_limiter.check(Action::Reaction, participant)?;
+
+    let (_, room) = find_with_room(&question_id).await?;
+    if !room.reaction_kinds.iter().any(|offered| offered == kind) {
+        return Err(Error::bad_request(format!("Unknown reaction `{kind}`")));
+    }
+
+    let actor = Actor::new(participant, source);
+    let (reacted, counted) = audit::atomically(|| async {
+        let removed = QuestionReaction::find_one_and_delete(doc! {
+            "question_id": question_id,
+            "participant_id": &participant_id,
+            "kind": kind,
+        })
+        .await?
+        .is_some();
+
+        if !removed {
+            // the unique index settles concurrent toggles of the same participant
+            QuestionReaction::create(QuestionReaction {
+                id: None,
+                question_id,
+                participant_id: participant_id.clone(),
+                kind: kind.to_owned(),
+            })
+            .await
+            .map_err(|err| already_voted(err, "You already reacted to this question"))?;
+        }
+
+        // the count follows the reaction just deleted or inserted
+        let delta = if removed { -1 } else { 1 };
+        let vote = Vote::reaction(kind, delta);
+        Ok((
+            !removed,
+            count_vote(&actor, &room, question_id, &vote).await?,
+        ))
+    })
+    .await?;
+    let question = publish_vote(room_server, counted).await?;
+
+    Ok(ToggledReaction {
+        kind: kind.to_owned(),
+        reacted,
+        question: PublicQuestion::from(question),
+    })
+}
+
+/// Downvote a question for the participant, or take the downvote back when they already
+/// have one.
+pub async fn toggle_downvote(
+    room_server: &RoomServerHandle,
+    rate_limiter: &RateLimiter,
+    participant: &Participant,
+    source: Source,
+    question_id: &str,
+) -> Result<ToggledDownvote, Error> {
+    let question_id = to_object_id(question_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+    let participant_id = participant
+        .id
+        .clone()
+        .ok_or_else(|| Error::bad_request("A participant id is required to downvote".into()))?;
+
+    rate_limiter.check(Action::Reaction, participant)?;
+
+    let (_, room) = find_with_room(&question_id).await?;
+    if !room.downvotes {
+        return Err(Error::bad_request("Room does not allow downvotes".into()));
+    }
+
+    let actor = Actor::new(participant, source);
+    let (downvoted, counted) = audit::atomically(|| async {
+        let removed = QuestionDownvote::find_one_and_delete(doc! {
+            "question_id": question_id,
+            "participant_id": &participant_id,
+        })
+        .await?
+        .is_some();
+
+        if !removed {
+            // the unique index settles concurrent toggles of the same participant
+            QuestionDownvote::create(QuestionDownvote {
+                id: None,
+                question_id,
+                participant_id: participant_id.clone(),
+            })
+            .await
+            .map_err(|err| already_voted(err, "You already downvoted this question"))?;
+        }
+
+        // the count follows the downvote just deleted or inserted
+        let delta = if removed { -1 } else { 1 };
+        let vote = Vote::Downvote(delta);
+        Ok((
+            !removed,
+            count_vote(&actor, &room, question_id, &vote).await?,
+        ))
+    })
+    .await?;
+    let question = publish_vote(room_server, counted).await?;
+
+    Ok(ToggledDownvote {
+        downvoted,
+        question: PublicQuestion::from(question),
+    })
+}
+
+/// A concurrent toggle of the same participant inserted the vote first.
+///
+fn already_voted(err: Error, message: &str) -> Error {
+    match err {
+        Error::Conflict(_) => Error::Conflict(message.into()),
+        err => err,
+    }
+}
+
+async fn find_with_room(question_id: &ObjectId) -> Result<(Question, Room), Error> {
+    let question = Question::find_by_id(question_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Question not found".into()))?;
+    Span::current().record("room_id", field::display(question.room_id));
+
+    let room = Room::find_by_id(&question.room_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Room not found".into()))?;
+
+    Ok((question, room))
+}
+
+/// Write reactions added up by the [`ReactionBuffer`](crate::reaction_buffer::ReactionBuffer)
+/// in one go, `counts` holding the number of each kind.
+pub async fn add_buffered_reactions(
+    room_server: &RoomServerHandle,
+    question_id: ObjectId,
+    counts: BTreeMap<String, i16>,
+) -> Result<Question, Error> {
+    let (_, room) = find_with_room(&question_id).await?;
+
+    add_vote(
+        room_server,
+        &Actor::server(),
+        &room,
+        question_id,
+        Vote::Reactions(counts),
+    )
+    .await
+}
+
+/// What a participant adds to or takes back from a question.
+///
+enum Vote {
+    /// Counts to add to each kind of reaction.
+    Reactions(BTreeMap<String, i16>),
+    Downvote(i16),
+}
+
+impl Vote {
+    fn reaction(kind: &str, delta: i16) -> Self {
+        Vote::Reactions(BTreeMap::from([(kind.to_owned(), delta)]))
+    }
+}
+
+/// A vote stored and recorded, not yet broadcast.
+///
+struct Counted {
+    previous: Question,
+    question: Question,
+    events: Vec<Event>,
+}
+
+/// Add reactions, to their kinds and the total, or downvotes to a question, then record and
+/// broadcast the change.
+async fn add_vote(
+    room_server: &RoomServerHandle,
+    actor: &Actor,
+    room: &Room,
+    question_id: ObjectId,
+    vote: Vote,
+) -> Result<Question, Error> {
+    let counted = audit::atomically(|| count_vote(actor, room, question_id, &vote)).await?;
+    publish_vote(room_server, counted).await
+}
+
+/// Store and record a vote, to be run within [`audit::atomically`].
+///
+async fn count_vote(
+    actor: &Actor,
+    room: &Room,
+    question_id: ObjectId,
+    vote: &Vote,
+) -> Result<Counted, Error> {
+    // both counts are always incremented so that questions stored before downvotes existed
+    // get the fields `rescore` matches on
+    let mut increments = doc! { "reaction_count": 0, "downvotes": 0 };
+    match vote {
+        Vote::Reactions(counts) => {
+            let total = counts.values().copied().map(i32::from).sum::<i32>();
+            increments.insert("reaction_count", total);
+            for (kind, delta) in counts {
+                increments.insert(format!("reactions.{kind}"), i32::from(*delta));
+            }
+        }
+        Vote::Downvote(delta) => {
+            increments.insert("downvotes", i32::from(*delta));
+        }
+    }
+
+    let previous = Question::find_one_and_update_before(
+        doc! { "_id": question_id },
+        doc! { "$inc": increments },
+    )
+    .await?
+    .ok_or_else(|| Error::NotFound("Question not found".into()))?;
+
+    let mut question = previous.clone();
+    let kind = match vote {
+        Vote::Reactions(counts) => {
+            for (kind, &delta) in counts {
+                question.reaction_count = question.reaction_count.saturating_add_signed(delta);
+                let count = question.reactions.entry(kind.clone()).or_default();
+                *count = count.saturating_add_signed(delta);
+            }
+            EventKind::QuestionReacted
+        }
+        Vote::Downvote(delta) => {
+            question.downvotes = question.downvotes.saturating_add_signed(*delta);
+            EventKind::QuestionDownvoted
+        }
+    };
+    rescore(room, &mut question).await?;
+
+    let events = audit::record(
+        actor,
+        vec![Change {
+            room_id: question.room_id,
+            question_id: question.id,
+            kind,
+            before: Some(&previous),
+            after: Some(&question),
+        }],
+    )
+    .await?;
+
+    Ok(Counted {
+        previous,
+        question,
+        events,
+    })
+}
+
+/// Broadcast a counted vote and send the reaction milestones it crossed to webhooks.
+///
+async fn publish_vote(room_server: &RoomServerHandle, counted: Counted) -> Result<Question, Error> {
+    let Counted {
+        previous,
+        question,
+        events,
+    } = counted;
+    projection::broadcast(room_server, &events).await;
+
+    // buffered reactions can jump past a milestone instead of landing on it
+    let crossed = settings::get()
+        .webhooks
+        .reaction_milestones
+        .iter()
+        .copied()
+        .filter(|&milestone| {
+            previous.reaction_count < milestone && question.reaction_count >= milestone
+        })
+        .collect::<Vec<_>>();
+    if reach_milestones(&question, &crossed).await? {
+        webhooks::emit(
+            question.room_id,
+            WebhookEvent::ReactionMilestone,
+            &PublicQuestion::from(question.clone()),
+        );
+    }
+
+    Ok(question)
+}
+
+/// Mark the milestones as reached on the question, returning whether one of them was not yet.
+///
+/// Taking a reaction back and adding it again crosses a milestone once more, it is only sent
+/// the first time.
+async fn reach_milestones(question: &Question, milestones: &[u16]) -> Result<bool, Error> {
+    let mut reached = false;
+    for &milestone in milestones {
+        let milestone = i32::from(milestone);
+        reached |= Question::find_one_and_update(
+            doc! { "_id": question.id, "reaction_milestones": { "$ne": milestone } },
+            doc! { "$addToSet": { "reaction_milestones": milestone } },
+        )
+        .await?
+        .is_some();
+    }
+
+    Ok(reached)
+}
+
+/// Store the confidence and hidden state matching the counts of `question`.
+///
+/// Nothing is stored when a concurrent vote changed the counts since, that vote stores its own.
+async fn rescore(room: &Room, question: &mut Question) -> Result<(), Error> {
+    question.rescore(room.hide_below);
+
+    Question::find_one_and_update(
+        doc! {
+            "_id": question.id,
+            "reaction_count": i32::from(question.reaction_count),
+            "downvotes": i32::from(question.downvotes),
+        },
+        doc! { "$set": { "confidence": question.confidence, "hidden": question.hidden } },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Questions of a room similar to `value`, most similar first.
+///
+pub async fn find_similar(room_id: &ObjectId, value: &str) -> Result<Vec<SimilarQuestion>, Error> {
+    let settings = &settings::get().duplicates;
+    if !settings.enabled {
+        return Ok(Vec::new());
+    }
+
+    let fingerprint = Fingerprint::new(value);
+    let (questions, _count) = Question::find_and_count(doc! { "room_id": room_id }, None).await?;
+
+    let mut similar = questions
+        .into_iter()
+        .filter_map(|question| {
+            let similarity = fingerprint.similarity(&Fingerprint::new(&question.value));
+            (similarity >= settings.threshold).then(|| SimilarQuestion {
+                question: PublicQuestion::from(question),
+                similarity,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    similar.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    similar.truncate(settings.max_suggestions);
+
+    Ok(similar)
+}
+
+/// Fold the question `from` into `into`: its votes are added to `into` and it is removed.
+///
+pub async fn merge_questions(
+    room_server: &RoomServerHandle,
+    actor: &Actor,
+    from: &str,
+    into: &str,
+) -> Result<PublicQuestion, Error> {
+    let from =
+        to_object_id(from).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+    let into =
+        to_object_id(into).map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    if from == into {
+        return Err(Error::bad_request(
+            "Cannot merge a question into itself".into(),
+        ));
+    }
+
+    let target = Question::find_by_id(&into)
+        .await?
+        .ok_or_else(|| Error::NotFound("Question to merge into not found".into()))?;
+    Span::current().record("room_id", field::display(target.room_id));
+
+    let room = Room::find_by_id(&target.room_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Room not found".into()))?;
+
+    let (updated_question, events) = audit::atomically(|| async {
+        // deleting first means a concurrent merge of the same question cannot count it twice
+        let merged = Question::find_one_and_delete(doc! { "_id": from, "room_id": target.room_id })
+            .await?
+            .ok_or_else(|| Error::NotFound("Question to merge not found in this room".into()))?;
+
+        let mut increments = doc! {
+            "reaction_count": i32::from(merged.reaction_count),
+            "downvotes": i32::from(merged.downvotes),
+        };
+        for (kind, count) in &merged.reactions {
+            increments.insert(format!("reactions.{kind}"), i32::from(*count));
+        }
+        let previous_target = Question::find_one_and_update_before(
+            doc! { "_id": into },
+            doc! { "$inc": increments.clone() },
+        )
+        .await?
+        .ok_or_else(|| Error::NotFound("Question to merge into not found".into()))?;
+        let mut updated_question = previous_target.clone();
+        updated_question.reaction_count = previous_target
+            .reaction_count
+            .saturating_add(merged.reaction_count);
+        updated_question.downvotes = previous_target.downvotes.saturating_add(merged.downvotes);
+        for (kind, count) in &merged.reactions {
+            let total = updated_question.reactions.entry(kind.clone()).or_default();
+            *total = total.saturating_add(*count);
+        }
+        rescore(&room, &mut updated_question).await?;
+
+        // the votes now count for `into`, whose own toggles cannot take them back
+        QuestionReaction::delete_many(doc! { "question_id": from }).await?;
+        QuestionDownvote::delete_many(doc! { "question_id": from }).await?;
+
+        let events = audit::record(
+            actor,
+            vec![
+                Change {
+                    room_id: target.room_id,
+                    question_id: Some(from),
+                    kind: EventKind::QuestionMerged,
+                    before: Some(&merged),
+                    after: None,
+                },
+                Change {
+                    room_id: target.room_id,
+                    question_id: Some(into),
+                    kind: EventKind::QuestionMerged,
+                    before: Some(&previous_target),
+                    after: Some(&updated_question),
+                },
+            ],
+        )
+        .await?;
+        Ok((updated_question, events))
+    })
+    .await?;
+    projection::broadcast(room_server, &events).await;
+
+    Ok(PublicQuestion::from(updated_question))
+}
+
+/// Mark a question as answered or not and broadcast it.
+///
+pub async fn set_answered(
+    room_server: &RoomServerHandle,
+    actor: &Actor,
+    question_id: &str,
+    answered: bool,
+) -> Result<PublicQuestion, Error> {
+    let question_id = to_object_id(question_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let (previous, question, events) = audit::atomically(|| async {
+        let previous = Question::find_one_and_update_before(
+            doc! { "_id": question_id },
+            doc! { "$set": bson!({
+                "answered": answered
+            }) },
+        )
+        .await?
+        .ok_or_else(|| Error::NotFound("Question not found".into()))?;
+
+        let question = Question {
+            answered,
+            ..previous.clone()
+        };
+
+        // answering twice changes nothing, so it is neither recorded, broadcast nor sent to
+        // webhooks again
+        if previous.answered == answered {
+            return Ok((previous, question, Vec::new()));
+        }
+
+        let kind = if answered {
+            EventKind::QuestionAnswered
+        } else {
+            EventKind::QuestionUnanswered
+        };
+        let events = audit::record(
+            actor,
+            vec![Change {
+                room_id: question.room_id,
+                question_id: question.id,
+                kind,
+                before: Some(&previous),
+                after: Some(&question),
+            }],
+        )
+        .await?;
+        Ok((previous, question, events))
+    })
+    .await?;
+    Span::current().record("room_id", field::display(previous.room_id));
+    projection::broadcast(room_server, &events).await;
+
+    let public_question = PublicQuestion::from(question.clone());
+
+    if answered && !previous.answered {
+        webhooks::emit(
+            question.room_id,
+            WebhookEvent::QuestionAnswered,
+            &public_question,
+        );
+    }
+
+    Ok(public_question)
+}
@@ -0,0 +1,154 @@
+use bson::{doc, serde_helpers::serialize_object_id_as_hex_string, Document};
+use serde::{Deserialize, Serialize};
+use wither::bson::{oid::ObjectId, DateTime};
+use wither::mongodb::options::FindOptions;
+
+use crate::{
+    errors::Error,
+    models::question::{PublicQuestion, Question},
+    utils::{models::ModelExt, similarity::normalize},
+};
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+/// Query string of the search routes.
+///
+#[derive(Debug, Clone, Deserialize)]
+pub struct SearchParams {
+    pub q: String,
+    pub answered: Option<bool>,
+    /// RFC 3339 date, questions asked at or after it.
+    pub from: Option<String>,
+    /// RFC 3339 date, questions asked before it.
+    pub to: Option<String>,
+    /// Only used by the search across rooms.
+    pub room_id: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// A piece of a question's text, `matched` when it is one of the searched terms.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct Fragment {
+    pub text: String,
+    pub matched: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub question: PublicQuestion,
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    pub room_id: ObjectId,
+    pub highlight: Vec<Fragment>,
+}
+
+/// Search questions with the MongoDB text index, best matches first.
+///
+/// The index is maintained by MongoDB itself, so created, edited and deleted questions are
+/// searchable right away.
+pub async fn search_questions(
+    room_id: Option<ObjectId>,
+    params: &SearchParams,
+) -> Result<Vec<SearchHit>, Error> {
+    let q = params.q.trim();
+    if q.is_empty() {
+        return Err(Error::bad_request("Search query cannot be empty".into()));
+    }
+
+    let mut filter = doc! { "$text": { "$search": q }, "hidden": { "$ne": true } };
+    if let Some(room_id) = room_id {
+        filter.insert("room_id", room_id);
+    }
+    if let Some(answered) = params.answered {
+        filter.insert("answered", answered);
+    }
+
+    // ids start with their creation time, which is what the date range filters on
+    let mut id_range = Document::new();
+    if let Some(from) = &params.from {
+        id_range.insert("$gte", id_at(from)?);
+    }
+    if let Some(to) = &params.to {
+        id_range.insert("$lt", id_at(to)?);
+    }
+    if !id_range.is_empty() {
+        filter.insert("_id", id_range);
+    }
+
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let options = FindOptions::builder()
+        .projection(doc! { "score": { "$meta": "textScore" } })
+        .sort(doc! { "score": { "$meta": "textScore" } })
+        .limit(limit)
+        .build();
+
+    let (questions, _count) = Question::find_and_count(filter, options).await?;
+
+    let terms = normalize(q)
+        .split_whitespace()
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    let hits = questions
+        .into_iter()
+        .map(|question| SearchHit {
+            room_id: question.room_id,
+            highlight: highlight(&question.value, &terms),
+            question: PublicQuestion::from(question),
+        })
+        .collect();
+
+    Ok(hits)
+}
+
+/// Smallest id a document created at `date` can have.
+///
+fn id_at(date: &str) -> Result<ObjectId, Error> {
+    let date = DateTime::parse_rfc3339_str(date)
+        .map_err(|_| Error::bad_request(format!("Invalid date: {date}")))?;
+    let secs = u32::try_from(date.timestamp_millis().div_euclid(1000))
+        .map_err(|_| Error::bad_request("Date out of range".into()))?;
+
+    Ok(ObjectId::from_parts(secs, [0; 5], [0; 3]))
+}
+
+/// Split `text` into fragments, marking the words that start with one of `terms`.
+///
+/// Prefix matching stands in for the stemming MongoDB does, so "deploying" is highlighted
+/// for "deploy".
+fn highlight(text: &str, terms: &[String]) -> Vec<Fragment> {
+    let mut fragments: Vec<Fragment> = Vec::new();
+    let mut push = |text: &str, matched: bool| match fragments.last_mut() {
+        Some(last) if last.matched == matched => last.text.push_str(text),
+        _ => fragments.push(Fragment {
+            text: text.to_owned(),
+            matched,
+        }),
+    };
+
+    let mut rest = text;
+    while !rest.is_empty() {
+        let word_len = rest
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or(rest.len());
+
+        if word_len == 0 {
+            let sep_len = rest.find(char::is_alphanumeric).unwrap_or(rest.len());
+            push(&rest[..sep_len], false);
+            rest = &rest[sep_len..];
+            continue;
+        }
+
+        let word = &rest[..word_len];
+        let lowercase = word.to_lowercase();
+        let matched = terms
+            .iter()
+            .any(|term| lowercase.starts_with(term.as_str()));
+        push(word, matched);
+        rest = &rest[word_len..];
+    }
+
+    fragments
+}
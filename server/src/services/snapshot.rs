@@ -0,0 +1,76 @@
+use serde::Serialize;
+use wither::bson::{doc, oid::ObjectId};
+use wither::mongodb::options::FindOptions;
+
+use crate::{
+    errors::Error,
+    models::{
+        question::{PublicQuestion, Question},
+        room::{PublicRoom, Room},
+    },
+    server::Joined,
+    utils::{models::ModelExt, to_object_id::to_object_id},
+};
+
+/// What a websocket session starts from before receiving the room's messages.
+///
+/// It is read after the session joined the room, so it already holds every change numbered up
+/// to `seq`. Messages numbered after it may be in it too, so clients apply them by question id:
+/// a `Create` of a question they already have replaces it instead of adding it twice.
+#[derive(Debug, Serialize)]
+pub struct RoomSnapshot {
+    pub seq: u64,
+    pub presence: usize,
+    pub room: PublicRoom,
+    pub questions: Vec<PublicQuestion>,
+    pub spotlight: Option<PublicQuestion>,
+}
+
+/// Read the room and its questions as listed by `GET /room/{id}/questions`.
+///
+pub async fn load(room_id: &str, joined: Joined) -> Result<RoomSnapshot, Error> {
+    let room_id = to_object_id(room_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let room = Room::find_by_id(&room_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Room not found".into()))?;
+
+    let options = FindOptions::builder().sort(room.question_sort()).build();
+    let (questions, _count) = Question::find_and_count(
+        doc! { "room_id": room_id, "hidden": { "$ne": true } },
+        options,
+    )
+    .await?;
+
+    let spotlight = match room.spotlight {
+        Some(question_id) => find_spotlight(&questions, question_id).await?,
+        None => None,
+    };
+
+    Ok(RoomSnapshot {
+        seq: joined.seq,
+        presence: joined.presence,
+        room: PublicRoom::from(room),
+        questions: questions.into_iter().map(Into::into).collect(),
+        spotlight,
+    })
+}
+
+/// The question in the spotlight, which a low score may have left out of the listed ones.
+///
+async fn find_spotlight(
+    questions: &[Question],
+    question_id: ObjectId,
+) -> Result<Option<PublicQuestion>, Error> {
+    if let Some(question) = questions
+        .iter()
+        .find(|question| question.id == Some(question_id))
+    {
+        return Ok(Some(PublicQuestion::from(question.clone())));
+    }
+
+    Ok(Question::find_by_id(&question_id)
+        .await?
+        .map(PublicQuestion::from))
+}
@@ -0,0 +1,154 @@
+use bson::doc;
+use futures::TryStreamExt;
+use wither::bson::oid::ObjectId;
+use wither::mongodb::options::FindOptions;
+
+use crate::{
+    errors::Error,
+    models::{
+        event::{Actor, EventKind},
+        question::{PublicQuestion, Question, Spotlight},
+        room::{PublicRoom, Room},
+    },
+    server::RoomServerHandle,
+    services::{
+        audit::{self, Change},
+        projection, question,
+    },
+    utils::{message_data::MessageData, models::ModelExt, to_object_id::to_object_id},
+};
+
+/// Put a question of the room in the spotlight, or clear it with `None`.
+///
+pub async fn set(
+    room_server: &RoomServerHandle,
+    actor: &Actor,
+    room_id: &str,
+    question_id: Option<&str>,
+) -> Result<PublicRoom, Error> {
+    let room_id = to_object_id(room_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+
+    let question_id = match question_id {
+        Some(question_id) => {
+            let question_id = to_object_id(question_id)
+                .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+            let question = Question::find_one(doc! { "_id": question_id, "room_id": room_id })
+                .await?
+                .ok_or_else(|| Error::NotFound("Question not found in this room".into()))?;
+            question.id
+        }
+        None => None,
+    };
+
+    spotlight(room_server, actor, room_id, question_id).await
+}
+
+/// Mark the question in the spotlight as answered and move on to the first unanswered one,
+/// in the room's sort mode. The spotlight is cleared once every question is answered.
+pub async fn next(
+    room_server: &RoomServerHandle,
+    actor: &Actor,
+    room_id: &str,
+) -> Result<PublicRoom, Error> {
+    let room_id = to_object_id(room_id)
+        .map_err(|_| Error::ParseObjectID("Failed to parse object id".into()))?;
+    let room = Room::find_by_id(&room_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Room not found".into()))?;
+
+    let mut filter = doc! { "room_id": room_id, "answered": false, "hidden": { "$ne": true } };
+    if let Some(current) = room.spotlight {
+        filter.insert("_id", doc! { "$ne": current });
+
+        // the question may have been merged into another one since
+        match question::set_answered(room_server, actor, &current.to_hex(), true).await {
+            Ok(_) | Err(Error::NotFound(_)) => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    let options = FindOptions::builder()
+        .sort(room.question_sort())
+        .limit(1)
+        .build();
+    let next = Question::find_cursor(filter, options)
+        .await?
+        .try_next()
+        .await
+        .map_err(Error::Wither)?;
+
+    spotlight(
+        room_server,
+        actor,
+        room_id,
+        next.and_then(|question| question.id),
+    )
+    .await
+}
+
+/// Send the question in the spotlight of `room` to its subscribers.
+///
+pub async fn broadcast(room_server: &RoomServerHandle, room: &Room) -> Result<(), Error> {
+    let question = match room.spotlight {
+        Some(question_id) => Question::find_by_id(&question_id).await?,
+        None => None,
+    };
+    let involves = question
+        .as_ref()
+        .map(Question::involves)
+        .unwrap_or_default();
+    let question = question.map(PublicQuestion::from);
+
+    let room_id = room.id.map(|id| id.to_string()).unwrap_or_default();
+    room_server
+        .send_message_involving(
+            room_id,
+            MessageData::spotlight(&Spotlight { question }),
+            involves,
+        )
+        .await;
+
+    Ok(())
+}
+
+async fn spotlight(
+    room_server: &RoomServerHandle,
+    actor: &Actor,
+    room_id: ObjectId,
+    question_id: Option<ObjectId>,
+) -> Result<PublicRoom, Error> {
+    let (room, events) = audit::atomically(|| async {
+        let previous = Room::find_one_and_update_before(
+            doc! { "_id": room_id },
+            doc! { "$set": { "spotlight": question_id } },
+        )
+        .await?
+        .ok_or_else(|| Error::NotFound("Room not found".into()))?;
+
+        let room = Room {
+            spotlight: question_id,
+            ..previous.clone()
+        };
+        if previous.spotlight == question_id {
+            return Ok((room, Vec::new()));
+        }
+
+        let events = audit::record(
+            actor,
+            vec![Change {
+                room_id,
+                question_id,
+                kind: EventKind::RoomSpotlighted,
+                before: Some(&previous),
+                after: Some(&room),
+            }],
+        )
+        .await?;
+        Ok((room, events))
+    })
+    .await?;
+    projection::broadcast(room_server, &events).await;
+
+    Ok(PublicRoom::from(room))
+}
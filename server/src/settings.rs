@@ -0,0 +1,474 @@
+use std::{
+    collections::HashMap, env, net::SocketAddr, num::NonZeroUsize, sync::OnceLock, thread,
+    time::Duration,
+};
+
+use config::{Config, ConfigError, Environment, File};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError, ValidationErrors};
+
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+pub const MAX_REACTION_KINDS: usize = 8;
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+#[derive(Debug, thiserror::Error)]
+pub enum SettingsError {
+    #[error("{0}")]
+    Config(#[from] ConfigError),
+
+    #[error("invalid configuration: {0}")]
+    Validation(#[from] ValidationErrors),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate)]
+#[serde(default)]
+pub struct Settings {
+    #[validate(nested)]
+    pub server: ServerSettings,
+    #[validate(nested)]
+    pub cors: CorsSettings,
+    #[validate(nested)]
+    pub websocket: WebsocketSettings,
+    #[validate(nested)]
+    pub database: DatabaseSettings,
+    #[validate(nested)]
+    pub telemetry: TelemetrySettings,
+    #[validate(nested)]
+    pub rate_limit: RateLimitSettings,
+    #[validate(nested)]
+    pub duplicates: DuplicateSettings,
+    #[validate(nested)]
+    pub webhooks: WebhookSettings,
+    #[validate(nested)]
+    pub events: EventSettings,
+    #[validate(nested)]
+    pub change_streams: ChangeStreamSettings,
+    #[validate(nested)]
+    pub reactions: ReactionSettings,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(default)]
+pub struct ServerSettings {
+    #[validate(length(min = 1, message = "at least one listen address is required"))]
+    pub listen: Vec<SocketAddr>,
+    /// Maximum size in bytes of a JSON or imported request body.
+    #[validate(range(min = 1))]
+    pub json_limit: usize,
+    /// Bearer token of host-only endpoints, which are disabled when unset.
+    pub host_token: Option<String>,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 8080))],
+            json_limit: 2 * 1024 * 1024,
+            host_token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(default)]
+pub struct CorsSettings {
+    /// Origins allowed to call the API, `*` allows any origin.
+    #[validate(length(min = 1, message = "at least one allowed origin is required"))]
+    pub allowed_origins: Vec<String>,
+    pub max_age_secs: usize,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["http://localhost:5173".into()],
+            max_age_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(default)]
+#[validate(schema(function = "validate_websocket"))]
+pub struct WebsocketSettings {
+    /// How often heartbeat pings are sent.
+    #[validate(range(min = 1))]
+    pub heartbeat_interval_secs: u64,
+    /// How long before lack of client response causes a timeout.
+    #[validate(range(min = 1))]
+    pub client_timeout_secs: u64,
+    #[validate(range(min = 1))]
+    pub max_frame_size: usize,
+    #[validate(range(min = 1))]
+    pub max_continuation_size: usize,
+    /// Threads the rooms are spread over, each owning the rooms whose id hashes to it.
+    #[validate(range(min = 1))]
+    pub room_shards: usize,
+    /// Rooms a session opened with `/rooms/subscribe` can follow at once.
+    #[validate(range(min = 1))]
+    pub max_subscriptions: usize,
+}
+
+impl WebsocketSettings {
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval_secs)
+    }
+
+    pub fn client_timeout(&self) -> Duration {
+        Duration::from_secs(self.client_timeout_secs)
+    }
+}
+
+impl Default for WebsocketSettings {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval_secs: 5,
+            client_timeout_secs: 10,
+            max_frame_size: 128 * 1024,
+            max_continuation_size: 2 * 1024 * 1024,
+            room_shards: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            max_subscriptions: 32,
+        }
+    }
+}
+
+fn validate_websocket(websocket: &WebsocketSettings) -> Result<(), ValidationError> {
+    if websocket.client_timeout_secs <= websocket.heartbeat_interval_secs {
+        return Err(ValidationError::new("client_timeout")
+            .with_message("client timeout must be longer than the heartbeat interval".into()));
+    }
+
+    if websocket.max_continuation_size < websocket.max_frame_size {
+        return Err(ValidationError::new("max_continuation_size")
+            .with_message("max continuation size cannot be smaller than a frame".into()));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(default)]
+pub struct DatabaseSettings {
+    #[validate(length(min = 1, message = "database url cannot be empty"))]
+    pub url: String,
+    #[validate(length(min = 1, message = "database name cannot be empty"))]
+    pub name: String,
+    #[validate(range(min = 1))]
+    pub max_pool_size: Option<u32>,
+    #[validate(range(min = 1))]
+    pub connect_timeout_secs: Option<u64>,
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        Self {
+            url: "mongodb://localhost:27017".into(),
+            name: "mongo".into(),
+            max_pool_size: None,
+            connect_timeout_secs: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Json,
+    Pretty,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(default)]
+pub struct TelemetrySettings {
+    /// `tracing` filter directives, `RUST_LOG` takes precedence when set.
+    #[validate(length(min = 1))]
+    pub filter: String,
+    pub format: LogFormat,
+    /// OTLP/gRPC collector spans are exported to, e.g. `http://localhost:4317`.
+    /// Requires the `otlp` feature.
+    pub otlp_endpoint: Option<String>,
+    #[validate(length(min = 1))]
+    pub service_name: String,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            filter: "info".into(),
+            format: LogFormat::Json,
+            otlp_endpoint: None,
+            service_name: "ama".into(),
+        }
+    }
+}
+
+/// A token bucket holding up to `burst` tokens, refilled at `per_minute` tokens a minute.
+///
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Validate)]
+pub struct BucketSettings {
+    #[validate(range(min = 1))]
+    pub burst: u32,
+    #[validate(range(min = 1))]
+    pub per_minute: u32,
+}
+
+impl BucketSettings {
+    const fn new(burst: u32, per_minute: u32) -> Self {
+        Self { burst, per_minute }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    pub enabled: bool,
+    #[validate(nested)]
+    pub questions_per_participant: BucketSettings,
+    #[validate(nested)]
+    pub questions_per_ip: BucketSettings,
+    #[validate(nested)]
+    pub reactions_per_participant: BucketSettings,
+    #[validate(nested)]
+    pub reactions_per_ip: BucketSettings,
+    /// Slow mode applied to new rooms unless they ask for their own, `0` disables it.
+    pub default_slow_mode_secs: u32,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            questions_per_participant: BucketSettings::new(5, 10),
+            // several participants can share an address behind a NAT
+            questions_per_ip: BucketSettings::new(30, 60),
+            reactions_per_participant: BucketSettings::new(20, 60),
+            reactions_per_ip: BucketSettings::new(100, 600),
+            default_slow_mode_secs: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(default)]
+pub struct DuplicateSettings {
+    pub enabled: bool,
+    /// Similarity from `0.0` to `1.0` above which an existing question is suggested.
+    #[validate(range(min = 0.0, max = 1.0))]
+    pub threshold: f64,
+    #[validate(range(min = 1))]
+    pub max_suggestions: usize,
+}
+
+impl Default for DuplicateSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 0.5,
+            max_suggestions: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(default)]
+pub struct WebhookSettings {
+    pub enabled: bool,
+    /// Attempts per delivery, the wait between two of them doubles each time.
+    #[validate(range(min = 1, max = 16))]
+    pub max_attempts: u32,
+    #[validate(range(min = 1))]
+    pub initial_backoff_secs: u64,
+    #[validate(range(min = 1))]
+    pub timeout_secs: u64,
+    /// Reaction counts at which `question.reaction_milestone` is sent.
+    pub reaction_milestones: Vec<u16>,
+}
+
+impl WebhookSettings {
+    pub fn initial_backoff(&self) -> Duration {
+        Duration::from_secs(self.initial_backoff_secs)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: 5,
+            initial_backoff_secs: 2,
+            timeout_secs: 10,
+            reaction_milestones: vec![10, 25, 50, 100],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate)]
+#[serde(default)]
+pub struct EventSettings {
+    /// Treat the event log as the source of truth: mutations and their events are stored in one
+    /// transaction, so rooms can always be rebuilt from it. Transactions need a replica set.
+    pub sourced: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(default)]
+pub struct ChangeStreamSettings {
+    /// Broadcast from MongoDB change streams instead of from the handlers, needs a replica set.
+    pub enabled: bool,
+    /// Key the resume token is stored under, distinct for each server watching the database.
+    #[validate(length(min = 1))]
+    pub resume_key: String,
+    #[validate(range(min = 1))]
+    pub retry_secs: u64,
+}
+
+impl ChangeStreamSettings {
+    pub fn retry(&self) -> Duration {
+        Duration::from_secs(self.retry_secs)
+    }
+}
+
+impl Default for ChangeStreamSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            resume_key: "default".into(),
+            retry_secs: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(default)]
+pub struct ReactionSettings {
+    /// Reactions offered by rooms created without their own, the first one is what the legacy
+    /// `PATCH /question/{id}/react` adds.
+    #[validate(custom(function = "validate_reaction_kinds"))]
+    pub kinds: Vec<String>,
+    /// Reaction counts of a question are broadcast at most once in this window, `0` sends
+    /// every change.
+    pub broadcast_window_ms: u64,
+    /// Keep the legacy upvotes in memory and write them to MongoDB every `flush_interval_ms`
+    /// instead of on each call.
+    pub buffered: bool,
+    #[validate(range(min = 1))]
+    pub flush_interval_ms: u64,
+}
+
+impl ReactionSettings {
+    pub fn broadcast_window(&self) -> Duration {
+        Duration::from_millis(self.broadcast_window_ms)
+    }
+
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_millis(self.flush_interval_ms)
+    }
+}
+
+impl Default for ReactionSettings {
+    fn default() -> Self {
+        Self {
+            kinds: vec!["+1".into(), "heart".into(), "laugh".into(), "clap".into()],
+            broadcast_window_ms: 250,
+            buffered: false,
+            flush_interval_ms: 1000,
+        }
+    }
+}
+
+/// A room offers 1 to [`MAX_REACTION_KINDS`] distinct kinds, made of characters that are safe
+/// in a MongoDB field path.
+pub fn validate_reaction_kinds(kinds: &[String]) -> Result<(), ValidationError> {
+    if kinds.is_empty() || kinds.len() > MAX_REACTION_KINDS {
+        return Err(ValidationError::new("reaction_kinds")
+            .with_message(format!("offer 1 to {MAX_REACTION_KINDS} reactions").into()));
+    }
+
+    let valid = |kind: &String| {
+        (1..=32).contains(&kind.len())
+            && kind
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-_".contains(c))
+    };
+    if let Some(kind) = kinds.iter().find(|kind| !valid(kind)) {
+        return Err(ValidationError::new("reaction_kinds").with_message(
+            format!("invalid reaction `{kind}`, use up to 32 of a-z, 0-9, +, - and _").into(),
+        ));
+    }
+
+    if kinds
+        .iter()
+        .enumerate()
+        .any(|(i, kind)| kinds[..i].contains(kind))
+    {
+        return Err(ValidationError::new("reaction_kinds")
+            .with_message("reactions must be distinct".into()));
+    }
+
+    Ok(())
+}
+
+impl Settings {
+    /// Build the settings from defaults, the TOML file and the environment, in that order.
+    ///
+    /// The file is read from `path`, falling back to `AMA_CONFIG` and then to an optional
+    /// `config.toml` in the working directory. Variables are prefixed with `AMA_` and use `__`
+    /// to reach nested keys, e.g. `AMA_SERVER__LISTEN=0.0.0.0:8080,[::]:8080`.
+    pub fn load(path: Option<&str>) -> Result<Self, SettingsError> {
+        let file = match path
+            .map(String::from)
+            .or_else(|| env::var("AMA_CONFIG").ok())
+        {
+            Some(path) => File::with_name(&path).required(true),
+            None => File::with_name(DEFAULT_CONFIG_PATH).required(false),
+        };
+
+        // DATABASE_URL and DATABASE_NAME predate the prefixed variables and are still honoured.
+        let legacy_env = ["DATABASE_URL", "DATABASE_NAME"]
+            .into_iter()
+            .filter_map(|key| env::var(key).ok().map(|value| (key.to_owned(), value)))
+            .collect::<HashMap<_, _>>();
+
+        let settings: Settings = Config::builder()
+            .add_source(file)
+            .add_source(
+                Environment::default()
+                    .separator("_")
+                    .source(Some(legacy_env)),
+            )
+            .add_source(
+                Environment::with_prefix("AMA")
+                    .prefix_separator("_")
+                    .separator("__")
+                    .list_separator(",")
+                    .with_list_parse_key("server.listen")
+                    .with_list_parse_key("cors.allowed_origins")
+                    .with_list_parse_key("reactions.kinds")
+                    .try_parsing(true),
+            )
+            .build()?
+            .try_deserialize()?;
+
+        settings.validate()?;
+
+        Ok(settings)
+    }
+}
+
+/// Make the settings available through [`get`]. Must be called once at startup.
+///
+pub fn init(settings: Settings) {
+    SETTINGS
+        .set(settings)
+        .expect("settings were already initialized");
+}
+
+pub fn get() -> &'static Settings {
+    SETTINGS.get().expect("settings are initialized at startup")
+}
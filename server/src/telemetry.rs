@@ -0,0 +1,112 @@
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+use crate::settings::{LogFormat, TelemetrySettings};
+
+#[derive(Debug, thiserror::Error)]
+pub enum TelemetryError {
+    #[error("{0}")]
+    Filter(#[from] tracing_subscriber::filter::ParseError),
+
+    #[error("{0}")]
+    Init(#[from] tracing_subscriber::util::TryInitError),
+
+    #[cfg(feature = "otlp")]
+    #[error("{0}")]
+    Otlp(#[from] opentelemetry::trace::TraceError),
+}
+
+/// Flushes pending spans to the collector when dropped.
+///
+pub struct TelemetryGuard {
+    #[cfg(feature = "otlp")]
+    provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("failed to flush spans: {err}");
+            }
+        }
+    }
+}
+
+/// Install the global subscriber: logs go to stdout in the configured format and, with the
+/// `otlp` feature and an endpoint configured, spans are also exported to a collector.
+///
+/// Records emitted through the `log` crate by our dependencies are forwarded as well.
+pub fn init(settings: &TelemetrySettings) -> Result<TelemetryGuard, TelemetryError> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(&settings.filter)?,
+    };
+
+    let fmt_layer = match settings.format {
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().boxed(),
+    };
+
+    #[cfg(feature = "otlp")]
+    let (otlp_layer, provider) = match settings.otlp_endpoint.as_deref() {
+        Some(endpoint) => {
+            let provider = otlp::provider(endpoint, &settings.service_name)?;
+            let layer = otlp::layer(&provider);
+            (Some(layer), Some(provider))
+        }
+        None => (None, None),
+    };
+    #[cfg(not(feature = "otlp"))]
+    let otlp_layer: Option<tracing_subscriber::layer::Identity> = None;
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer)
+        .with(otlp_layer)
+        .try_init()?;
+
+    #[cfg(not(feature = "otlp"))]
+    if settings.otlp_endpoint.is_some() {
+        tracing::warn!("otlp_endpoint is set but the server was built without the otlp feature");
+    }
+
+    Ok(TelemetryGuard {
+        #[cfg(feature = "otlp")]
+        provider,
+    })
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use opentelemetry::{trace::TracerProvider as _, KeyValue};
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+    use tracing::Subscriber;
+    use tracing_subscriber::{registry::LookupSpan, Layer};
+
+    pub fn provider(
+        endpoint: &str,
+        service_name: &str,
+    ) -> Result<TracerProvider, opentelemetry::trace::TraceError> {
+        let exporter = SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+
+        Ok(TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new([KeyValue::new(
+                "service.name",
+                service_name.to_owned(),
+            )]))
+            .build())
+    }
+
+    pub fn layer<S>(provider: &TracerProvider) -> impl Layer<S>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("ama"))
+    }
+}
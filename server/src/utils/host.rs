@@ -0,0 +1,40 @@
+use actix_web::{dev::Payload, http::header, FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+
+use crate::{errors::Error, settings};
+
+/// Guard for host-only endpoints.
+///
+/// Requests must send `Authorization: Bearer <server.host_token>`. Without a configured token
+/// the endpoints are disabled altogether.
+#[derive(Debug, Clone, Copy)]
+pub struct Host;
+
+impl Host {
+    fn authorize(req: &HttpRequest) -> Result<Self, Error> {
+        let Some(expected) = settings::get().server.host_token.as_deref() else {
+            return Err(Error::Forbidden("Host endpoints are disabled".into()));
+        };
+
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+
+        match token {
+            Some(token) if token == expected => Ok(Host),
+            _ => Err(Error::Unauthorized("Invalid or missing host token".into())),
+        }
+    }
+}
+
+impl FromRequest for Host {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Host::authorize(req))
+    }
+}
@@ -0,0 +1,69 @@
+use pulldown_cmark::{html, Options, Parser};
+
+/// Render Markdown to HTML that is safe to insert in a page.
+///
+/// Raw HTML in the source is kept only as far as the sanitizer allows, scripts, event handlers
+/// and `javascript:` links are stripped, and links get `rel="noopener noreferrer"`.
+pub fn render(markdown: &str) -> String {
+    let options =
+        Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES | Options::ENABLE_TASKLISTS;
+    let parser = Parser::new_ext(markdown, options);
+
+    let mut unsafe_html = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, parser);
+
+    ammonia::clean(&unsafe_html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+
+    #[test]
+    fn renders_markdown() {
+        assert_eq!(
+            render("**bold** and ~~gone~~"),
+            "<p><strong>bold</strong> and <del>gone</del></p>\n"
+        );
+    }
+
+    #[test]
+    fn strips_scripts() {
+        let html = render("before <script>alert(1)</script> after");
+
+        assert!(!html.contains("<script"), "{html}");
+        assert!(!html.contains("alert(1)"), "{html}");
+        assert!(html.contains("before"), "{html}");
+    }
+
+    #[test]
+    fn strips_javascript_links() {
+        for markdown in [
+            "[click](javascript:alert(1))",
+            "<a href=\"javascript:alert(1)\">click</a>",
+        ] {
+            let html = render(markdown);
+
+            assert!(!html.contains("javascript:"), "{html}");
+            assert!(html.contains("click"), "{html}");
+        }
+    }
+
+    #[test]
+    fn strips_event_attributes() {
+        let html =
+            render("<img src=\"x.png\" onerror=\"alert(1)\"> <b onclick=\"alert(1)\">hi</b>");
+
+        assert!(!html.contains("onerror"), "{html}");
+        assert!(!html.contains("onclick"), "{html}");
+        assert!(html.contains("<b>hi</b>"), "{html}");
+    }
+
+    #[test]
+    fn marks_links_noopener() {
+        let html = render("[site](https://example.com)");
+
+        assert!(html.contains("href=\"https://example.com\""), "{html}");
+        assert!(html.contains("rel=\"noopener noreferrer\""), "{html}");
+    }
+}
@@ -5,6 +5,15 @@ pub enum MessageKind {
     Create,
     Update,
     Delete,
+    Merge,
+    Close,
+    Spotlight,
+    Poll,
+    Reaction,
+    Announcement,
+    Snapshot,
+    Unsubscribed,
+    Error,
 }
 
 #[derive(Serialize)]
@@ -30,6 +39,42 @@ impl<'a, T: Serialize + 'a> MessageData<'a, T> {
         Self::new(MessageKind::Delete, data)
     }
 
+    pub fn merge(data: &'a T) -> Self {
+        Self::new(MessageKind::Merge, data)
+    }
+
+    pub fn close(data: &'a T) -> Self {
+        Self::new(MessageKind::Close, data)
+    }
+
+    pub fn spotlight(data: &'a T) -> Self {
+        Self::new(MessageKind::Spotlight, data)
+    }
+
+    pub fn poll(data: &'a T) -> Self {
+        Self::new(MessageKind::Poll, data)
+    }
+
+    pub fn reaction(data: &'a T) -> Self {
+        Self::new(MessageKind::Reaction, data)
+    }
+
+    pub fn snapshot(data: &'a T) -> Self {
+        Self::new(MessageKind::Snapshot, data)
+    }
+
+    pub fn unsubscribed(data: &'a T) -> Self {
+        Self::new(MessageKind::Unsubscribed, data)
+    }
+
+    pub fn announcement(data: &'a T) -> Self {
+        Self::new(MessageKind::Announcement, data)
+    }
+
+    pub fn error(data: &'a T) -> Self {
+        Self::new(MessageKind::Error, data)
+    }
+
     pub fn to_json(&self) -> Result<String, serde_json::Error> {
         serde_json::to_string(self)
     }
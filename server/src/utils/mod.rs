@@ -1,3 +1,7 @@
+pub mod host;
+pub mod markdown;
 pub mod message_data;
 pub mod models;
+pub mod participant;
+pub mod similarity;
 pub mod to_object_id;
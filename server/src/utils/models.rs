@@ -1,13 +1,45 @@
 use async_trait::async_trait;
 use futures::stream::TryStreamExt;
-use validator::Validate;
+use validator::{Validate, ValidationErrors};
 use wither::bson::{doc, oid::ObjectId, Document};
+use wither::mongodb::error::Error as MongoError;
 use wither::mongodb::options::FindOneAndUpdateOptions;
 use wither::mongodb::options::FindOptions;
+use wither::mongodb::options::ReplaceOptions;
 use wither::mongodb::options::ReturnDocument;
-use wither::Model as WitherModel;
+use wither::{Model as WitherModel, ModelCursor, WitherError};
 
-use crate::{database, errors::Error};
+use crate::{
+    database,
+    errors::{is_duplicate_key, Error},
+    metrics::time_db,
+};
+
+/// One `field: message` line per failed rule that has a message.
+///
+pub fn validation_messages(errors: &ValidationErrors) -> Vec<String> {
+    errors
+        .field_errors()
+        .iter()
+        .flat_map(|(field, errors)| {
+            errors.iter().filter_map(move |e| {
+                e.message
+                    .clone()
+                    .map(|message| format!("{}: {}", field, message))
+            })
+        })
+        .collect()
+}
+
+/// A unique index refusing the document is a conflict, any other failure the server's.
+///
+fn create_error(error: MongoError) -> Error {
+    if is_duplicate_key(&error) {
+        Error::Conflict("The resource already exists".into())
+    } else {
+        Error::InternalServerError("Error while creating the resource".into())
+    }
+}
 
 #[async_trait]
 pub trait ModelExt
@@ -17,63 +49,284 @@ where
     async fn create(mut model: Self) -> Result<Self, Error> {
         let connection = database::connection().await;
         model.validate().map_err(|error| {
-            let error_messages: Vec<String> = error
-                .field_errors()
-                .iter()
-                .flat_map(|(field, errors)| {
-                    errors.iter().filter_map(move |e| {
-                        e.message
-                            .clone()
-                            .map(|message| format!("{}: {}", field, message))
-                    })
-                })
-                .collect();
-
-            Error::bad_request(format!("Validation errors: {:?}", error_messages))
-        })?;
-        model.save(connection, None).await.map_err(|_error| {
-            Error::InternalServerError("Error while creating the resource".into())
+            Error::bad_request(format!(
+                "Validation errors: {:?}",
+                validation_messages(&error)
+            ))
         })?;
 
+        if let Some(session) = database::session() {
+            let document = model.document_from_instance().map_err(Error::Wither)?;
+            let result = time_db(
+                Self::COLLECTION_NAME,
+                "insert_one",
+                Self::collection(connection).insert_one_with_session(
+                    document,
+                    None,
+                    &mut *session.lock().await,
+                ),
+            )
+            .await
+            .map_err(create_error)?;
+            if let Some(id) = result.inserted_id.as_object_id() {
+                model.set_id(id);
+            }
+
+            return Ok(model);
+        }
+
+        time_db(Self::COLLECTION_NAME, "save", model.save(connection, None))
+            .await
+            .map_err(|error| match error {
+                WitherError::Mongo(error) => create_error(error),
+                _ => Error::InternalServerError("Error while creating the resource".into()),
+            })?;
+
         Ok(model)
     }
 
+    /// Insert already validated models in a single round trip.
+    ///
+    async fn create_many(models: Vec<Self>) -> Result<usize, Error> {
+        if models.is_empty() {
+            return Ok(0);
+        }
+
+        let connection = database::connection().await;
+        let documents = models
+            .iter()
+            .map(|model| model.document_from_instance())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::Wither)?;
+
+        let collection = Self::collection(connection);
+        let result = time_db(Self::COLLECTION_NAME, "insert_many", async {
+            match database::session() {
+                Some(session) => {
+                    collection
+                        .insert_many_with_session(documents, None, &mut *session.lock().await)
+                        .await
+                }
+                None => collection.insert_many(documents, None).await,
+            }
+        })
+        .await
+        .map_err(Error::Mongo)?;
+
+        Ok(result.inserted_ids.len())
+    }
+
     async fn find_and_count<O>(query: Document, options: O) -> Result<(Vec<Self>, u64), Error>
     where
         O: Into<Option<FindOptions>> + Send,
     {
         let connection = database::connection().await;
 
-        let count = Self::collection(connection)
-            .count_documents(query.clone(), None)
-            .await
-            .map_err(Error::Mongo)?;
+        let count = time_db(
+            Self::COLLECTION_NAME,
+            "count_documents",
+            Self::collection(connection).count_documents(query.clone(), None),
+        )
+        .await
+        .map_err(Error::Mongo)?;
 
-        let items = <Self as WitherModel>::find(connection, query, options.into())
-            .await
-            .map_err(Error::Wither)?
-            .try_collect::<Vec<Self>>()
-            .await
-            .map_err(Error::Wither)?;
+        let items = time_db(Self::COLLECTION_NAME, "find", async {
+            <Self as WitherModel>::find(connection, query, options.into())
+                .await?
+                .try_collect::<Vec<Self>>()
+                .await
+        })
+        .await
+        .map_err(Error::Wither)?;
 
         Ok((items, count))
     }
 
+    /// Like [`find_and_count`](ModelExt::find_and_count) but yielding documents as they come,
+    /// for results too large to hold in memory.
+    async fn find_cursor<O>(query: Document, options: O) -> Result<ModelCursor<Self>, Error>
+    where
+        O: Into<Option<FindOptions>> + Send,
+    {
+        let connection = database::connection().await;
+
+        time_db(
+            Self::COLLECTION_NAME,
+            "find",
+            <Self as WitherModel>::find(connection, query, options.into()),
+        )
+        .await
+        .map_err(Error::Wither)
+    }
+
+    async fn find_one(query: Document) -> Result<Option<Self>, Error> {
+        let connection = database::connection().await;
+        time_db(
+            Self::COLLECTION_NAME,
+            "find_one",
+            <Self as WitherModel>::find_one(connection, query, None),
+        )
+        .await
+        .map_err(Error::Wither)
+    }
+
     async fn find_by_id(id: &ObjectId) -> Result<Option<Self>, Error> {
         let connection = database::connection().await;
-        <Self as WitherModel>::find_one(connection, doc! { "_id": id }, None)
-            .await
-            .map_err(|_| Error::NotFound("Error while fetching the room".into()))
+        time_db(
+            Self::COLLECTION_NAME,
+            "find_one",
+            <Self as WitherModel>::find_one(connection, doc! { "_id": id }, None),
+        )
+        .await
+        .map_err(|_| Error::NotFound("Error while fetching the room".into()))
     }
 
     async fn find_one_and_update(query: Document, update: Document) -> Result<Option<Self>, Error> {
+        Self::find_one_and_update_returning(query, update, ReturnDocument::After).await
+    }
+
+    /// Like [`find_one_and_update`](ModelExt::find_one_and_update) but returning the document
+    /// as it was before the update, so callers can tell exactly what changed.
+    async fn find_one_and_update_before(
+        query: Document,
+        update: Document,
+    ) -> Result<Option<Self>, Error> {
+        Self::find_one_and_update_returning(query, update, ReturnDocument::Before).await
+    }
+
+    async fn find_one_and_update_returning(
+        query: Document,
+        update: Document,
+        return_document: ReturnDocument,
+    ) -> Result<Option<Self>, Error> {
         let connection = database::connection().await;
         let options = FindOneAndUpdateOptions::builder()
-            .return_document(ReturnDocument::After)
+            .return_document(return_document)
             .build();
 
-        <Self as WitherModel>::find_one_and_update(connection, query, update, options)
+        if let Some(session) = database::session() {
+            let document = time_db(
+                Self::COLLECTION_NAME,
+                "find_one_and_update",
+                Self::collection(connection).find_one_and_update_with_session(
+                    query,
+                    update,
+                    options,
+                    &mut *session.lock().await,
+                ),
+            )
+            .await
+            .map_err(Error::Mongo)?;
+
+            return document
+                .map(Self::instance_from_document)
+                .transpose()
+                .map_err(Error::Wither);
+        }
+
+        time_db(
+            Self::COLLECTION_NAME,
+            "find_one_and_update",
+            <Self as WitherModel>::find_one_and_update(connection, query, update, options),
+        )
+        .await
+        .map_err(Error::Wither)
+    }
+
+    async fn find_one_and_delete(query: Document) -> Result<Option<Self>, Error> {
+        let connection = database::connection().await;
+
+        if let Some(session) = database::session() {
+            let document = time_db(
+                Self::COLLECTION_NAME,
+                "find_one_and_delete",
+                Self::collection(connection).find_one_and_delete_with_session(
+                    query,
+                    None,
+                    &mut *session.lock().await,
+                ),
+            )
+            .await
+            .map_err(Error::Mongo)?;
+
+            return document
+                .map(Self::instance_from_document)
+                .transpose()
+                .map_err(Error::Wither);
+        }
+
+        time_db(
+            Self::COLLECTION_NAME,
+            "find_one_and_delete",
+            <Self as WitherModel>::find_one_and_delete(connection, query, None),
+        )
+        .await
+        .map_err(Error::Wither)
+    }
+
+    async fn delete_many(query: Document) -> Result<u64, Error> {
+        let connection = database::connection().await;
+
+        if let Some(session) = database::session() {
+            let result = time_db(
+                Self::COLLECTION_NAME,
+                "delete_many",
+                Self::collection(connection).delete_many_with_session(
+                    query,
+                    None,
+                    &mut *session.lock().await,
+                ),
+            )
             .await
-            .map_err(Error::Wither)
+            .map_err(Error::Mongo)?;
+
+            return Ok(result.deleted_count);
+        }
+
+        let result = time_db(
+            Self::COLLECTION_NAME,
+            "delete_many",
+            <Self as WitherModel>::delete_many(connection, query, None),
+        )
+        .await
+        .map_err(Error::Wither)?;
+
+        Ok(result.deleted_count)
+    }
+
+    /// Insert the model, or overwrite the stored document with the same id.
+    ///
+    async fn upsert(model: &Self) -> Result<(), Error> {
+        let connection = database::connection().await;
+        let id = model.id().ok_or_else(|| {
+            Error::InternalServerError("Cannot upsert a resource without an id".into())
+        })?;
+        let document = model.document_from_instance().map_err(Error::Wither)?;
+        let options = ReplaceOptions::builder().upsert(true).build();
+
+        let collection = Self::collection(connection);
+        time_db(Self::COLLECTION_NAME, "replace_one", async {
+            match database::session() {
+                Some(session) => {
+                    collection
+                        .replace_one_with_session(
+                            doc! { "_id": id },
+                            document,
+                            options,
+                            &mut *session.lock().await,
+                        )
+                        .await
+                }
+                None => {
+                    collection
+                        .replace_one(doc! { "_id": id }, document, options)
+                        .await
+                }
+            }
+        })
+        .await
+        .map_err(Error::Mongo)?;
+
+        Ok(())
     }
 }
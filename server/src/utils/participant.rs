@@ -0,0 +1,60 @@
+use std::{convert::Infallible, net::IpAddr};
+
+use actix_web::{dev::Payload, web::Query, FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+use serde::Deserialize;
+
+/// Header carrying the id the web client generates and keeps in local storage.
+pub const PARTICIPANT_HEADER: &str = "x-participant-id";
+
+const MAX_PARTICIPANT_ID_LEN: usize = 64;
+
+/// Anonymous participant behind a request.
+///
+/// Browsers cannot set headers on websocket upgrades, so the id is also read from the
+/// `participant_id` query parameter.
+#[derive(Debug, Clone, Default)]
+pub struct Participant {
+    pub id: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+
+#[derive(Deserialize)]
+struct ParticipantQuery {
+    participant_id: Option<String>,
+}
+
+impl Participant {
+    fn from_request(req: &HttpRequest) -> Self {
+        let from_header = req
+            .headers()
+            .get(PARTICIPANT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+
+        let from_query = || {
+            Query::<ParticipantQuery>::from_query(req.query_string())
+                .ok()
+                .and_then(|query| query.into_inner().participant_id)
+        };
+
+        let id = from_header
+            .or_else(from_query)
+            .map(|id| id.trim().to_owned())
+            .filter(|id| !id.is_empty() && id.len() <= MAX_PARTICIPANT_ID_LEN);
+
+        Self {
+            id,
+            ip: req.peer_addr().map(|addr| addr.ip()),
+        }
+    }
+}
+
+impl FromRequest for Participant {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(Participant::from_request(req)))
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashSet;
+
+/// Words that carry no meaning on their own and only add noise to the comparison.
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "can", "do", "does", "for", "how", "i", "in", "is",
+    "it", "of", "on", "or", "the", "to", "we", "what", "when", "why", "will", "with", "you",
+    "your",
+];
+
+const SHINGLE_SIZE: usize = 3;
+
+/// Lowercase `text`, replace punctuation with spaces and drop stop words.
+///
+pub fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !STOP_WORDS.contains(word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Character shingles of a normalized text, compared with the Jaccard index.
+///
+/// Character shingles rather than whole words so that typos and plurals still overlap.
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    normalized: String,
+    shingles: HashSet<String>,
+}
+
+impl Fingerprint {
+    pub fn new(text: &str) -> Self {
+        let normalized = normalize(text);
+        let chars = normalized.chars().collect::<Vec<_>>();
+
+        let shingles = if chars.len() < SHINGLE_SIZE {
+            HashSet::from([normalized.clone()])
+        } else {
+            chars
+                .windows(SHINGLE_SIZE)
+                .map(|window| window.iter().collect())
+                .collect()
+        };
+
+        Self {
+            normalized,
+            shingles,
+        }
+    }
+
+    /// Similarity between `0.0` (nothing in common) and `1.0` (same normalized text).
+    ///
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        if self.normalized.is_empty() || other.normalized.is_empty() {
+            return 0.0;
+        }
+
+        if self.normalized == other.normalized {
+            return 1.0;
+        }
+
+        let intersection = self.shingles.intersection(&other.shingles).count();
+        let union = self.shingles.len() + other.shingles.len() - intersection;
+
+        intersection as f64 / union as f64
+    }
+}
@@ -0,0 +1,653 @@
+use std::{future::Future, sync::LazyLock, time::Duration};
+
+use bson::{doc, serde_helpers::serialize_object_id_as_hex_string, Document};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use tracing::Instrument;
+use wither::bson::{oid::ObjectId, DateTime};
+
+use crate::{
+    errors::Error,
+    models::webhook::{DeliveryStatus, Webhook, WebhookDelivery, WebhookEvent},
+    settings::{self, WebhookSettings},
+    utils::models::ModelExt,
+};
+
+const SIGNATURE_HEADER: &str = "x-ama-signature";
+const TIMESTAMP_HEADER: &str = "x-ama-timestamp";
+const EVENT_HEADER: &str = "x-ama-event";
+const DELIVERY_HEADER: &str = "x-ama-delivery";
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(settings::get().webhooks.timeout())
+        .user_agent("ama-webhooks")
+        .build()
+        .expect("Failed to build the webhook HTTP client")
+});
+
+#[derive(Serialize)]
+struct Payload<'a, T: Serialize> {
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    id: ObjectId,
+    event: WebhookEvent,
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    room_id: ObjectId,
+    data: &'a T,
+}
+
+/// Notify the webhooks of `room_id` subscribed to `event`, in the background.
+///
+/// Deliveries are recorded before the first attempt and retried with exponential backoff, so
+/// the caller never waits on a receiver.
+pub fn emit<T: Serialize>(room_id: ObjectId, event: WebhookEvent, data: &T) {
+    if !settings::get().webhooks.enabled {
+        return;
+    }
+
+    let data = match serde_json::to_value(data) {
+        Ok(data) => data,
+        Err(err) => {
+            tracing::error!(error = %err, ?event, "failed to serialize webhook payload");
+            return;
+        }
+    };
+
+    tokio::spawn(
+        async move {
+            if let Err(err) = dispatch(room_id, event, data).await {
+                tracing::error!(error = %err, "failed to dispatch webhooks");
+            }
+        }
+        .instrument(tracing::info_span!("webhooks", ?event, %room_id)),
+    );
+}
+
+async fn dispatch(
+    room_id: ObjectId,
+    event: WebhookEvent,
+    data: serde_json::Value,
+) -> Result<(), Error> {
+    let (webhooks, _count) =
+        Webhook::find_and_count(doc! { "room_id": room_id, "events": event.as_str() }, None)
+            .await?;
+
+    for webhook in webhooks {
+        let delivery_id = ObjectId::new();
+        let payload = Payload {
+            id: delivery_id,
+            event,
+            room_id,
+            data: &data,
+        };
+        let payload = serde_json::to_string(&payload)
+            .map_err(|err| Error::InternalServerError(err.to_string()))?;
+
+        let delivery = WebhookDelivery::new(delivery_id, webhook.id.unwrap(), event, payload);
+        let delivery = WebhookDelivery::create(delivery).await?;
+
+        tokio::spawn(deliver(webhook, delivery).instrument(tracing::info_span!(
+            "webhook_delivery",
+            delivery_id = %delivery_id
+        )));
+    }
+
+    Ok(())
+}
+
+/// Pick up the deliveries still pending when the server last stopped, their retries only
+/// lived in memory.
+///
+/// Those whose webhook was deleted since are marked as failed. A delivery sent just before the
+/// server stopped may be sent again, receivers can skip it by its `x-ama-delivery` header.
+pub async fn resume() -> Result<(), Error> {
+    let settings = &settings::get().webhooks;
+    if !settings.enabled {
+        return Ok(());
+    }
+
+    let (deliveries, count) =
+        WebhookDelivery::find_and_count(doc! { "status": DeliveryStatus::Pending.as_str() }, None)
+            .await?;
+
+    for delivery in deliveries {
+        let delivery_id = delivery.id.unwrap();
+        match Webhook::find_by_id(&delivery.webhook_id).await? {
+            Some(webhook) => {
+                tokio::spawn(deliver(webhook, delivery).instrument(tracing::info_span!(
+                    "webhook_delivery",
+                    delivery_id = %delivery_id
+                )));
+            }
+            None => {
+                let attempt = Attempt {
+                    number: delivery.attempts,
+                    status: DeliveryStatus::Failed,
+                    response_status: delivery.response_status,
+                    error: Some("webhook was deleted".into()),
+                    retry_in: None,
+                };
+                record(delivery_id, &attempt).await;
+            }
+        }
+    }
+
+    if count > 0 {
+        tracing::info!(count, "resumed pending webhook deliveries");
+    }
+
+    Ok(())
+}
+
+/// One try at a delivery, as recorded on it.
+///
+#[derive(Debug, Clone, PartialEq)]
+struct Attempt {
+    number: u32,
+    status: DeliveryStatus,
+    response_status: Option<u16>,
+    error: Option<String>,
+    /// Wait before the next attempt, while the delivery is still pending.
+    retry_in: Option<Duration>,
+}
+
+impl Attempt {
+    /// The update storing the attempt on its delivery.
+    ///
+    fn to_update(&self) -> Document {
+        let next_attempt_at = self.retry_in.map(|retry_in| {
+            DateTime::from_millis(DateTime::now().timestamp_millis() + retry_in.as_millis() as i64)
+        });
+
+        doc! { "$set": {
+            "status": self.status.as_str(),
+            "attempts": self.number,
+            "response_status": self.response_status.map(i32::from),
+            "error": &self.error,
+            "last_attempt_at": DateTime::now(),
+            "next_attempt_at": next_attempt_at,
+        } }
+    }
+}
+
+async fn deliver(webhook: Webhook, delivery: WebhookDelivery) {
+    let settings = &settings::get().webhooks;
+    let delivery_id = delivery.id.unwrap();
+
+    retry(
+        &CLIENT,
+        settings.into(),
+        &webhook,
+        &delivery,
+        |attempt| async move {
+            record(delivery_id, &attempt).await;
+        },
+    )
+    .await;
+}
+
+async fn record(delivery_id: ObjectId, attempt: &Attempt) {
+    tracing::info!(
+        attempt = attempt.number,
+        status = ?attempt.status,
+        response_status = attempt.response_status,
+        error = attempt.error,
+        "webhook delivery attempted"
+    );
+
+    let recorded =
+        WebhookDelivery::find_one_and_update(doc! { "_id": delivery_id }, attempt.to_update())
+            .await;
+    if let Err(err) = recorded {
+        tracing::error!(error = %err, "failed to record webhook delivery");
+    }
+}
+
+/// How many times and how patiently a delivery is tried.
+///
+#[derive(Debug, Clone, Copy)]
+struct Retries {
+    max_attempts: u32,
+    initial_backoff: Duration,
+}
+
+impl From<&WebhookSettings> for Retries {
+    fn from(settings: &WebhookSettings) -> Self {
+        Self {
+            max_attempts: settings.max_attempts,
+            initial_backoff: settings.initial_backoff(),
+        }
+    }
+}
+
+/// Post the delivery until the receiver answers with a success status or attempts run out,
+/// handing each attempt to `record`.
+///
+/// Attempts made before the server restarted count towards `max_attempts`, and the next one
+/// waits until the `next_attempt_at` they left.
+async fn retry<F, Fut>(
+    client: &reqwest::Client,
+    retries: Retries,
+    webhook: &Webhook,
+    delivery: &WebhookDelivery,
+    mut record: F,
+) where
+    F: FnMut(Attempt) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    if let Some(next_attempt_at) = delivery.next_attempt_at {
+        let wait = next_attempt_at.timestamp_millis() - DateTime::now().timestamp_millis();
+        tokio::time::sleep(Duration::from_millis(wait.max(0) as u64)).await;
+    }
+
+    let first = delivery.attempts + 1;
+    if first > retries.max_attempts {
+        // stopped after the last attempt but before recording it
+        record(Attempt {
+            number: delivery.attempts,
+            status: DeliveryStatus::Failed,
+            response_status: delivery.response_status,
+            error: delivery.error.clone(),
+            retry_in: None,
+        })
+        .await;
+        return;
+    }
+
+    for number in first..=retries.max_attempts {
+        let last = number == retries.max_attempts;
+        let failed = if last {
+            DeliveryStatus::Failed
+        } else {
+            DeliveryStatus::Pending
+        };
+
+        let (status, response_status, error) = match send(client, webhook, delivery).await {
+            Ok(code) if code.is_success() => (DeliveryStatus::Delivered, Some(code.as_u16()), None),
+            Ok(code) => (
+                failed,
+                Some(code.as_u16()),
+                Some(format!("receiver answered {code}")),
+            ),
+            Err(err) => (failed, None, Some(err.to_string())),
+        };
+        let retry_in =
+            (status == DeliveryStatus::Pending).then(|| backoff(retries.initial_backoff, number));
+
+        record(Attempt {
+            number,
+            status,
+            response_status,
+            error,
+            retry_in,
+        })
+        .await;
+
+        match retry_in {
+            Some(retry_in) => tokio::time::sleep(retry_in).await,
+            None => return,
+        }
+    }
+}
+
+/// Wait after the failed attempt `number`, doubling from `initial` with each attempt.
+///
+fn backoff(initial: Duration, number: u32) -> Duration {
+    initial * 2u32.pow(number - 1)
+}
+
+async fn send(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    delivery: &WebhookDelivery,
+) -> Result<reqwest::StatusCode, reqwest::Error> {
+    let timestamp = DateTime::now().timestamp_millis() / 1000;
+    let signature = sign(&webhook.secret, timestamp, &delivery.payload);
+
+    let response = client
+        .post(&webhook.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .header(TIMESTAMP_HEADER, timestamp.to_string())
+        .header(EVENT_HEADER, delivery.event.as_str())
+        .header(DELIVERY_HEADER, delivery.id.unwrap().to_hex())
+        .body(delivery.payload.clone())
+        .send()
+        .await?;
+
+    Ok(response.status())
+}
+
+/// `sha256=` followed by the hex HMAC-SHA256 of `{timestamp}.{payload}` keyed with the secret.
+///
+/// Signing the timestamp along with the body lets receivers reject replayed deliveries.
+fn sign(secret: &str, timestamp: i64, payload: &str) -> String {
+    // unwrap: HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    const SECRET: &str = "secret";
+
+    /// A request as the stand-in receiver got it.
+    struct Received {
+        headers: HashMap<String, String>,
+        body: String,
+    }
+
+    /// Stand in for a receiver on a local port, answering each request with the next of
+    /// `statuses` and with the last one once they run out.
+    async fn receiver(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let log = received.clone();
+        tokio::spawn(async move {
+            for answered in 0.. {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut stream).await;
+                log.lock().unwrap().push(request);
+
+                let status = statuses[usize::min(answered, statuses.len() - 1)];
+                let response = format!(
+                    "HTTP/1.1 {status} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (url, received)
+    }
+
+    async fn read_request(stream: &mut TcpStream) -> Received {
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 4096];
+        let head_len = loop {
+            let read = stream.read(&mut chunk).await.unwrap();
+            buffer.extend_from_slice(&chunk[..read]);
+            if let Some(end) = buffer.windows(4).position(|bytes| bytes == b"\r\n\r\n") {
+                break end + 4;
+            }
+        };
+
+        let headers = String::from_utf8_lossy(&buffer[..head_len])
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_owned()))
+            .collect::<HashMap<_, _>>();
+
+        let body_len = headers
+            .get("content-length")
+            .map_or(0, |length| length.parse::<usize>().unwrap());
+        while buffer.len() < head_len + body_len {
+            let read = stream.read(&mut chunk).await.unwrap();
+            buffer.extend_from_slice(&chunk[..read]);
+        }
+
+        Received {
+            headers,
+            body: String::from_utf8(buffer[head_len..head_len + body_len].to_vec()).unwrap(),
+        }
+    }
+
+    fn delivery(url: &str) -> (Webhook, WebhookDelivery) {
+        let webhook = Webhook::new(
+            ObjectId::new(),
+            url.to_owned(),
+            vec![WebhookEvent::QuestionCreated],
+            SECRET.to_owned(),
+        );
+        let delivery = WebhookDelivery::new(
+            ObjectId::new(),
+            ObjectId::new(),
+            WebhookEvent::QuestionCreated,
+            r#"{"event":"question.created"}"#.to_owned(),
+        );
+
+        (webhook, delivery)
+    }
+
+    fn retries(max_attempts: u32) -> Retries {
+        Retries {
+            max_attempts,
+            initial_backoff: Duration::from_millis(10),
+        }
+    }
+
+    async fn run(retries: Retries, webhook: &Webhook, delivery: &WebhookDelivery) -> Vec<Attempt> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+
+        let mut attempts = Vec::new();
+        retry(&client, retries, webhook, delivery, |attempt| {
+            attempts.push(attempt);
+            async {}
+        })
+        .await;
+
+        attempts
+    }
+
+    #[test]
+    fn signs_timestamp_and_payload() {
+        assert_eq!(
+            sign(SECRET, 1_700_000_000, r#"{"id":1}"#),
+            "sha256=3dd1b9aef568d75f6790a84bd2e5dfa1f44409eef3cbdbd3f10b837376100c11"
+        );
+        assert_ne!(
+            sign(SECRET, 1_700_000_001, r#"{"id":1}"#),
+            sign(SECRET, 1_700_000_000, r#"{"id":1}"#)
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_with_each_attempt() {
+        let initial = Duration::from_secs(2);
+
+        assert_eq!(backoff(initial, 1), Duration::from_secs(2));
+        assert_eq!(backoff(initial, 2), Duration::from_secs(4));
+        assert_eq!(backoff(initial, 4), Duration::from_secs(16));
+    }
+
+    #[tokio::test]
+    async fn sends_signed_deliveries() {
+        let (url, received) = receiver(vec![204]).await;
+        let (webhook, delivery) = delivery(&url);
+
+        let attempts = run(retries(3), &webhook, &delivery).await;
+
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].status, DeliveryStatus::Delivered);
+        assert_eq!(attempts[0].response_status, Some(204));
+
+        let received = received.lock().unwrap();
+        let request = &received[0];
+        let timestamp = request.headers[TIMESTAMP_HEADER].parse::<i64>().unwrap();
+        assert_eq!(request.body, delivery.payload);
+        assert_eq!(
+            request.headers[SIGNATURE_HEADER],
+            sign(SECRET, timestamp, &request.body)
+        );
+        assert_eq!(request.headers[EVENT_HEADER], "question.created");
+        assert_eq!(
+            request.headers[DELIVERY_HEADER],
+            delivery.id.unwrap().to_hex()
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_with_backoff_until_delivered() {
+        let (url, received) = receiver(vec![500, 503, 200]).await;
+        let (webhook, delivery) = delivery(&url);
+
+        let started = Instant::now();
+        let attempts = run(retries(5), &webhook, &delivery).await;
+
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert_eq!(
+            attempts,
+            vec![
+                Attempt {
+                    number: 1,
+                    status: DeliveryStatus::Pending,
+                    response_status: Some(500),
+                    error: Some("receiver answered 500 Internal Server Error".into()),
+                    retry_in: Some(Duration::from_millis(10)),
+                },
+                Attempt {
+                    number: 2,
+                    status: DeliveryStatus::Pending,
+                    response_status: Some(503),
+                    error: Some("receiver answered 503 Service Unavailable".into()),
+                    retry_in: Some(Duration::from_millis(20)),
+                },
+                Attempt {
+                    number: 3,
+                    status: DeliveryStatus::Delivered,
+                    response_status: Some(200),
+                    error: None,
+                    retry_in: None,
+                },
+            ]
+        );
+
+        // every attempt sends the same delivery
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        assert!(received
+            .iter()
+            .all(|request| request.headers[DELIVERY_HEADER] == delivery.id.unwrap().to_hex()));
+    }
+
+    #[tokio::test]
+    async fn fails_after_the_last_attempt() {
+        let (url, received) = receiver(vec![500]).await;
+        let (webhook, delivery) = delivery(&url);
+
+        let attempts = run(retries(2), &webhook, &delivery).await;
+
+        let statuses = attempts
+            .iter()
+            .map(|attempt| attempt.status)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![DeliveryStatus::Pending, DeliveryStatus::Failed]
+        );
+        assert_eq!(attempts[1].retry_in, None);
+        assert_eq!(received.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn records_unreachable_receivers() {
+        // nothing listens on the port once the listener is dropped
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        drop(listener);
+        let (webhook, delivery) = delivery(&url);
+
+        let attempts = run(retries(1), &webhook, &delivery).await;
+
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].status, DeliveryStatus::Failed);
+        assert_eq!(attempts[0].response_status, None);
+        assert!(attempts[0].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn resumes_where_the_previous_run_stopped() {
+        let (url, received) = receiver(vec![200]).await;
+        let (webhook, mut delivery) = delivery(&url);
+        delivery.attempts = 2;
+        delivery.next_attempt_at = Some(DateTime::from_millis(
+            DateTime::now().timestamp_millis() + 20,
+        ));
+
+        let started = Instant::now();
+        let attempts = run(retries(3), &webhook, &delivery).await;
+
+        assert!(started.elapsed() >= Duration::from_millis(15));
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].number, 3);
+        assert_eq!(attempts[0].status, DeliveryStatus::Delivered);
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn fails_resumed_deliveries_without_attempts_left() {
+        let (url, received) = receiver(vec![200]).await;
+        let (webhook, mut delivery) = delivery(&url);
+        delivery.attempts = 3;
+        delivery.response_status = Some(500);
+
+        let attempts = run(retries(3), &webhook, &delivery).await;
+
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].number, 3);
+        assert_eq!(attempts[0].status, DeliveryStatus::Failed);
+        assert_eq!(attempts[0].response_status, Some(500));
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn records_attempts_in_the_delivery_status_log() {
+        let pending = Attempt {
+            number: 2,
+            status: DeliveryStatus::Pending,
+            response_status: Some(500),
+            error: Some("receiver answered 500 Internal Server Error".into()),
+            retry_in: Some(Duration::from_secs(4)),
+        };
+        let update = pending.to_update();
+        let set = update.get_document("$set").unwrap();
+
+        assert_eq!(set.get_str("status"), Ok("pending"));
+        assert_eq!(set.get_i32("attempts"), Ok(2));
+        assert_eq!(set.get_i32("response_status"), Ok(500));
+        assert_eq!(
+            set.get_str("error"),
+            Ok("receiver answered 500 Internal Server Error")
+        );
+        let last_attempt_at = set.get_datetime("last_attempt_at").unwrap();
+        let next_attempt_at = set.get_datetime("next_attempt_at").unwrap();
+        assert!(next_attempt_at.timestamp_millis() - last_attempt_at.timestamp_millis() >= 3_900);
+
+        let delivered = Attempt {
+            number: 3,
+            status: DeliveryStatus::Delivered,
+            response_status: Some(200),
+            error: None,
+            retry_in: None,
+        };
+        let update = delivered.to_update();
+        let set = update.get_document("$set").unwrap();
+
+        assert_eq!(set.get_str("status"), Ok("delivered"));
+        assert!(set.is_null("error"));
+        assert!(set.is_null("next_attempt_at"));
+    }
+}
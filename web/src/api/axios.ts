@@ -1,5 +1,11 @@
 import Axios from "axios";
+import { getParticipantId } from "./participant";
 
 export const axios = Axios.create({
   baseURL: import.meta.env.VITE_API_BASE_URL,
 });
+
+axios.interceptors.request.use((config) => {
+  config.headers.set("x-participant-id", getParticipantId());
+  return config;
+});
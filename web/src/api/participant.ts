@@ -0,0 +1,13 @@
+const STORAGE_KEY = "participant_id";
+
+// Anonymous id the server keys rate limits, votes and reactions on, kept across reloads.
+export const getParticipantId = () => {
+  let participantId = localStorage.getItem(STORAGE_KEY);
+
+  if (!participantId) {
+    participantId = crypto.randomUUID();
+    localStorage.setItem(STORAGE_KEY, participantId);
+  }
+
+  return participantId;
+};
@@ -1,9 +1,14 @@
 import { axios } from "./axios";
 
-export const createQuestion = async (roomId: string, question: string) => {
+export const createQuestion = async (
+  roomId: string,
+  question: string,
+  force = false
+) => {
   const response = await axios.post("/question", {
     room_id: roomId,
     value: question,
+    force,
   });
   return response.data;
 };
@@ -1,14 +1,67 @@
 import { useRef, useCallback, useState, useEffect } from "react";
 import { toast } from "sonner";
+import { getParticipantId } from "../api/participant";
 import { Question } from "../types";
 
-type Message = {
-  kind: "Create" | "Update";
-  data: Question;
+type ReactionCounts = {
+  question_id: string;
+  reactions: Record<string, number>;
+  reaction_count: number;
+  downvotes: number;
+  score: number;
+  hidden: boolean;
+};
+
+type Snapshot = {
+  seq: number;
+  presence: number;
+  questions: Question[];
+  spotlight: Question | null;
+};
+
+type Message = { seq?: number } & (
+  | {
+      kind: "Snapshot";
+      data: Snapshot;
+    }
+  | {
+      kind: "Create" | "Update";
+      data: Question;
+    }
+  | {
+      kind: "Reaction";
+      data: ReactionCounts;
+    }
+  | {
+      kind: "Announcement";
+      data: { id: string; body: string; created_at: string };
+    }
+  | {
+      kind: "Error";
+      data: { status: number; error: string };
+    }
+);
+
+// the snapshot may already hold a question a message right after it creates
+const upsert = (questions: Question[] | null, question: Question) => {
+  if (!questions) {
+    return [question];
+  }
+
+  const questionIndex = questions.findIndex(({ id }) => id === question.id);
+  if (questionIndex < 0) {
+    return [...questions, question];
+  }
+
+  const newState = [...questions];
+  newState[questionIndex] = question;
+
+  return newState;
 };
 
 const useQuestions = (roomId: string | undefined) => {
   const socketRef = useRef<WebSocket | null>(null);
+  const snapshotSeqRef = useRef(0);
 
   const [questions, setQuestions] = useState<Question[] | null>(null);
 
@@ -18,7 +71,7 @@ const useQuestions = (roomId: string | undefined) => {
     }
 
     const socket = new WebSocket(
-      `${import.meta.env.VITE_WS_BASE_URL}/room/subscribe/${roomId}`
+      `${import.meta.env.VITE_WS_BASE_URL}/room/subscribe/${roomId}?participant_id=${encodeURIComponent(getParticipantId())}`
     );
 
     socketRef.current = socket;
@@ -35,11 +88,24 @@ const useQuestions = (roomId: string | undefined) => {
       try {
         const message: Message = JSON.parse(rawMessage.data);
 
+        // changes the snapshot already shows
+        if (
+          message.kind !== "Snapshot" &&
+          message.seq !== undefined &&
+          message.seq <= snapshotSeqRef.current
+        ) {
+          return;
+        }
+
         switch (message.kind) {
+          case "Snapshot": {
+            snapshotSeqRef.current = message.data.seq;
+            setQuestions(message.data.questions);
+            break;
+          }
+
           case "Create": {
-            setQuestions((prevState) =>
-              prevState?.length ? [...prevState, message.data] : [message.data]
-            );
+            setQuestions((prevState) => upsert(prevState, message.data));
             break;
           }
 
@@ -66,6 +132,44 @@ const useQuestions = (roomId: string | undefined) => {
             break;
           }
 
+          case "Reaction": {
+            const counts = message.data;
+
+            setQuestions((prevState) =>
+              prevState
+                ? prevState.map((question) =>
+                    question.id === counts.question_id
+                      ? {
+                          ...question,
+                          reaction_count: counts.reaction_count,
+                          downvotes: counts.downvotes,
+                          score: counts.score,
+                          hidden: counts.hidden,
+                          reactions: {
+                            ...question.reactions,
+                            ...counts.reactions,
+                          },
+                        }
+                      : question
+                  )
+                : prevState
+            );
+
+            break;
+          }
+
+          case "Announcement": {
+            toast.info(message.data.body);
+            break;
+          }
+
+          case "Error": {
+            toast.error(message.data.error);
+            // without a snapshot there is nothing to wait for
+            setQuestions((prevState) => prevState ?? []);
+            break;
+          }
+
           default:
             break;
         }
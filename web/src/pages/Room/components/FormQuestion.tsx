@@ -5,6 +5,7 @@ import { toast } from "sonner";
 import { createQuestion } from "../../../api/question";
 import Button from "../../../components/Button";
 import TextInput from "../../../components/TextInput";
+import { Question } from "../../../types";
 
 type FormQuestionProps = {
   roomId: string;
@@ -17,26 +18,32 @@ const FormQuestion = ({ roomId }: FormQuestionProps) => {
   const [loading, setLoading] = useState(false);
   const [questionFieldError, setQuestionFieldError] = useState<boolean>(false);
 
-  const handleCreateQuestion = async (event: React.FormEvent) => {
-    event.preventDefault();
-
-    if (!question) {
-      setQuestionFieldError(true);
-      inputRef.current?.focus();
-      return;
-    }
-
+  const submitQuestion = async (force: boolean) => {
     try {
       setLoading(true);
       setQuestionFieldError(false);
 
-      await createQuestion(roomId, question);
+      await createQuestion(roomId, question, force);
       setQuestion("");
 
       toast(`You've successfully created a question!`);
     } catch (err: unknown) {
       console.log(err);
 
+      // a similar question was already asked, the participant can still ask theirs
+      if (err instanceof AxiosError && err.response?.status === 409) {
+        const suggestions: Question[] = err.response.data.suggestions ?? [];
+
+        toast.warning("A similar question has already been asked", {
+          description: suggestions[0]?.value,
+          action: {
+            label: "Ask anyway",
+            onClick: () => submitQuestion(true),
+          },
+        });
+        return;
+      }
+
       if (err instanceof AxiosError) {
         toast.error(err.response?.data.error ?? err.message);
         return;
@@ -48,6 +55,18 @@ const FormQuestion = ({ roomId }: FormQuestionProps) => {
     }
   };
 
+  const handleCreateQuestion = async (event: React.FormEvent) => {
+    event.preventDefault();
+
+    if (!question) {
+      setQuestionFieldError(true);
+      inputRef.current?.focus();
+      return;
+    }
+
+    await submitQuestion(false);
+  };
+
   return (
     <form className="w-full" onSubmit={handleCreateQuestion}>
       <TextInput
@@ -1,8 +1,4 @@
-import { AxiosError } from "axios";
-import { useCallback, useEffect, useState } from "react";
 import ContentLoader from "react-content-loader";
-import { toast } from "sonner";
-import { getQuestions } from "../../../api/question";
 import useQuestions from "../../../hooks/use-questions";
 import QuestionCard from "./Question";
 
@@ -24,28 +20,10 @@ type ListQuestionsProps = {
 };
 
 const ListQuestions = ({ roomId }: ListQuestionsProps) => {
-  const { connect, disconnect, questions, setQuestions } = useQuestions(roomId);
+  // the socket starts with a snapshot of the questions, then keeps them up to date
+  const { questions } = useQuestions(roomId);
 
-  const [loading, setLoading] = useState(true);
-
-  const loadQuestions = useCallback(async () => {
-    if (!roomId) return;
-
-    try {
-      const questions = await getQuestions(roomId);
-      setQuestions(questions);
-    } catch (err) {
-      toast.error(
-        (err as AxiosError)?.response?.data?.error ?? (err as AxiosError)
-      );
-    } finally {
-      setLoading(false);
-    }
-  }, [roomId, setQuestions]);
-
-  useEffect(() => {
-    loadQuestions();
-  }, [loadQuestions, disconnect]);
+  const loading = questions === null;
 
   if (loading) {
     return <ListQuestionsLoader />;
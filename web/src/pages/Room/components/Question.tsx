@@ -19,7 +19,9 @@ const QuestionCard = ({ question }: QuestionProps) => {
   return (
     <div className="text-zinc-100 leading-relaxed">
       <span>{question.value}</span>
-      <div></div>
+      {question.display_name && (
+        <div className="text-sm text-zinc-500">— {question.display_name}</div>
+      )}
       <Link asChild>
         <button className="mt-3" onClick={handleReact}>
           <ArrowUp size={16} />
@@ -3,5 +3,10 @@ export type Question = {
   room_id: string;
   answered: boolean;
   reaction_count: number;
+  reactions: Record<string, number>;
+  downvotes: number;
+  score: number;
+  hidden: boolean;
   value: string;
+  display_name: string | null;
 };